@@ -0,0 +1,100 @@
+//! Throughput of `POST /bus` through the whole server, routing, GPS filter,
+//! live position store and write queue included, against a SQLite database
+//! holding 1k, 10k and 100k buses. Rate limits are off, and every fix is
+//! written through as it arrives, so each one costs its `current_location`
+//! write rather than waiting for a flush.
+//!
+//!     cargo bench --bench ingest
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use rocket::figment::providers::Serialized;
+use rocket::figment::Figment;
+use rocket::http::{ContentType, Status};
+use rocket::local::blocking::Client;
+use rocket::serde::json::json;
+
+const FLEETS: [usize; 3] = [1_000, 10_000, 100_000];
+
+/// Fixes posted per fleet, unless `BUDGET` runs out first.
+const FIXES: usize = 5_000;
+
+const BUDGET: Duration = Duration::from_secs(3);
+
+/// The configuration from `Rocket.toml`, pointed at a database of its own in
+/// `dir` and with nothing in the way of a client posting as fast as it can.
+fn figment(dir: &Path) -> Figment {
+    rocket::Config::figment().merge(Serialized::globals(json!({
+        "log_level": "off",
+        "admin": { "token": "bench" },
+        "databases": { "diesel": { "url": dir.join("db.sqlite") } },
+        "backup": { "dir": dir.join("backups") },
+        "rate_limit": { "groups": [] },
+        "live_positions": { "durability": "immediate" },
+    })))
+}
+
+/// A fresh database in a directory of its own, migrated by igniting the
+/// server once and then filled with `size` buses.
+fn fleet(size: usize) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("bus-server-ingest-{}-{size}", std::process::id()));
+    std::fs::create_dir_all(&dir).expect("temporary directory");
+    drop(Client::untracked(bus_server::custom(figment(&dir))).expect("server"));
+    let url = dir.join("db.sqlite");
+    let mut conn =
+        SqliteConnection::establish(url.to_str().expect("utf-8 path")).expect("database");
+    diesel::sql_query(format!(
+        "WITH RECURSIVE n (i) AS (SELECT 0 UNION ALL SELECT i + 1 FROM n WHERE i + 1 < {size})
+        INSERT INTO current_location (busid, latitude, longitude)
+        SELECT printf('bus%07d', i), 31.1, 77.2 FROM n"
+    ))
+    .execute(&mut conn)
+    .expect("seed");
+    dir
+}
+
+fn run(client: &Client, size: usize) -> (usize, Duration) {
+    let start = Instant::now();
+    let mut written = 0;
+    // A multiplicative step visits buses in an order the page cache can't
+    // predict, the way fixes from a fleet arrive.
+    let mut n = 0;
+    while written < FIXES && start.elapsed() < BUDGET {
+        n = (n + 7_919) % size;
+        let fix = json!({
+            "busid": format!("bus{n:07}"),
+            "latitude": 31.1 + written as f32 * 1e-5,
+            "longitude": 77.2,
+            "timestamp": written as i64 + 1,
+        });
+        let response = client
+            .post("/bus")
+            .header(ContentType::JSON)
+            .body(fix.to_string())
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(
+            response.into_json::<bool>(),
+            Some(true),
+            "bus {n} was refused"
+        );
+        written += 1;
+    }
+    (written, start.elapsed())
+}
+
+fn main() {
+    println!("{:>8} {:>12}", "buses", "fixes/s");
+    for size in FLEETS {
+        let dir = fleet(size);
+        let client = Client::untracked(bus_server::custom(figment(&dir))).expect("server");
+        let (written, elapsed) = run(&client, size);
+        let rate = written as f64 / elapsed.as_secs_f64();
+        println!("{size:>8} {rate:>12.0}");
+        drop(client);
+        std::fs::remove_dir_all(&dir).expect("clean up");
+    }
+}
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE location_history;
+ALTER TABLE current_location DROP COLUMN recorded_at;
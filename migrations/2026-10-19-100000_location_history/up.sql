@@ -0,0 +1,12 @@
+-- Your SQL goes here
+ALTER TABLE current_location ADD COLUMN recorded_at BIGINT NOT NULL DEFAULT 0;
+
+CREATE TABLE location_history (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    busid CHAR(12) NOT NULL,
+    latitude FLOAT NOT NULL,
+    longitude FLOAT NOT NULL,
+    recorded_at BIGINT NOT NULL
+);
+
+CREATE INDEX location_history_busid_recorded_at ON location_history (busid, recorded_at);
@@ -0,0 +1,12 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE location_history DROP COLUMN odometer;
+ALTER TABLE location_history DROP COLUMN altitude;
+ALTER TABLE location_history DROP COLUMN accuracy;
+ALTER TABLE location_history DROP COLUMN heading;
+ALTER TABLE location_history DROP COLUMN speed;
+
+ALTER TABLE current_location DROP COLUMN odometer;
+ALTER TABLE current_location DROP COLUMN altitude;
+ALTER TABLE current_location DROP COLUMN accuracy;
+ALTER TABLE current_location DROP COLUMN heading;
+ALTER TABLE current_location DROP COLUMN speed;
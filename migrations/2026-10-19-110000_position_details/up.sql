@@ -0,0 +1,12 @@
+-- Your SQL goes here
+ALTER TABLE current_location ADD COLUMN speed FLOAT;
+ALTER TABLE current_location ADD COLUMN heading FLOAT;
+ALTER TABLE current_location ADD COLUMN accuracy FLOAT;
+ALTER TABLE current_location ADD COLUMN altitude FLOAT;
+ALTER TABLE current_location ADD COLUMN odometer DOUBLE;
+
+ALTER TABLE location_history ADD COLUMN speed FLOAT;
+ALTER TABLE location_history ADD COLUMN heading FLOAT;
+ALTER TABLE location_history ADD COLUMN accuracy FLOAT;
+ALTER TABLE location_history ADD COLUMN altitude FLOAT;
+ALTER TABLE location_history ADD COLUMN odometer DOUBLE;
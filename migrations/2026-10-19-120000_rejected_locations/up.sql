@@ -0,0 +1,13 @@
+-- Your SQL goes here
+CREATE TABLE rejected_locations (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    busid CHAR(12) NOT NULL,
+    latitude FLOAT NOT NULL,
+    longitude FLOAT NOT NULL,
+    recorded_at BIGINT NOT NULL,
+    accuracy FLOAT,
+    reason TEXT NOT NULL,
+    received_at BIGINT NOT NULL
+);
+
+CREATE INDEX rejected_locations_busid ON rejected_locations (busid);
@@ -0,0 +1,10 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE location_history DROP COLUMN distance_along;
+ALTER TABLE location_history DROP COLUMN snapped_longitude;
+ALTER TABLE location_history DROP COLUMN snapped_latitude;
+
+ALTER TABLE current_location DROP COLUMN distance_along;
+ALTER TABLE current_location DROP COLUMN snapped_longitude;
+ALTER TABLE current_location DROP COLUMN snapped_latitude;
+
+DROP TABLE route_shapes;
@@ -0,0 +1,13 @@
+-- Your SQL goes here
+CREATE TABLE route_shapes (
+    busid CHAR(12) NOT NULL PRIMARY KEY,
+    shape TEXT NOT NULL
+);
+
+ALTER TABLE current_location ADD COLUMN snapped_latitude FLOAT;
+ALTER TABLE current_location ADD COLUMN snapped_longitude FLOAT;
+ALTER TABLE current_location ADD COLUMN distance_along FLOAT;
+
+ALTER TABLE location_history ADD COLUMN snapped_latitude FLOAT;
+ALTER TABLE location_history ADD COLUMN snapped_longitude FLOAT;
+ALTER TABLE location_history ADD COLUMN distance_along FLOAT;
@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE location_history DROP COLUMN direction;
+ALTER TABLE current_location DROP COLUMN direction;
+
+ALTER TABLE routes DROP COLUMN inbound;
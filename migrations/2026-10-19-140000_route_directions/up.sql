@@ -0,0 +1,5 @@
+-- Your SQL goes here
+ALTER TABLE routes ADD COLUMN inbound TEXT;
+
+ALTER TABLE current_location ADD COLUMN direction TEXT;
+ALTER TABLE location_history ADD COLUMN direction TEXT;
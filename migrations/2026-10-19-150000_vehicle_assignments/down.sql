@@ -0,0 +1,7 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE location_history DROP COLUMN routeid;
+
+DROP TABLE vehicle_assignments;
+
+ALTER TABLE route_shapes RENAME COLUMN routeid TO busid;
+ALTER TABLE routes RENAME COLUMN routeid TO busid;
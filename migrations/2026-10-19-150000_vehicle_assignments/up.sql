@@ -0,0 +1,21 @@
+-- Your SQL goes here
+ALTER TABLE routes RENAME COLUMN busid TO routeid;
+ALTER TABLE route_shapes RENAME COLUMN busid TO routeid;
+
+CREATE TABLE vehicle_assignments (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    busid CHAR(12) NOT NULL,
+    routeid CHAR(12) NOT NULL,
+    valid_from BIGINT NOT NULL,
+    valid_to BIGINT
+);
+
+CREATE INDEX vehicle_assignments_busid ON vehicle_assignments (busid, valid_to);
+CREATE INDEX vehicle_assignments_routeid ON vehicle_assignments (routeid, valid_to);
+
+-- Every existing route was run by the bus it was named after.
+INSERT INTO vehicle_assignments (busid, routeid, valid_from)
+SELECT routeid, routeid, CAST(strftime('%s', 'now') AS BIGINT) FROM routes;
+
+ALTER TABLE location_history ADD COLUMN routeid TEXT;
+UPDATE location_history SET routeid = busid WHERE busid IN (SELECT routeid FROM routes);
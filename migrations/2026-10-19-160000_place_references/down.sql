@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TRIGGER place_location_cascade_busses;
+DROP TRIGGER place_location_restrict;
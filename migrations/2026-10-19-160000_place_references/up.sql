@@ -0,0 +1,19 @@
+-- Your SQL goes here
+-- Stop lists in `routes` are `|`-joined place ids, which a FOREIGN KEY clause
+-- cannot express, so the reference is enforced with triggers instead.
+CREATE TRIGGER place_location_restrict
+BEFORE DELETE ON place_location
+WHEN EXISTS (
+    SELECT 1 FROM routes
+    WHERE instr('|' || placeid || '|', '|' || OLD.busid || '|') > 0
+       OR instr('|' || IFNULL(inbound, '') || '|', '|' || OLD.busid || '|') > 0
+)
+BEGIN
+    SELECT RAISE(ABORT, 'FOREIGN KEY constraint failed');
+END;
+
+CREATE TRIGGER place_location_cascade_busses
+AFTER DELETE ON place_location
+BEGIN
+    DELETE FROM busses WHERE placeid = OLD.busid;
+END;
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX place_location_latitude_longitude;
+DROP INDEX current_location_latitude_longitude;
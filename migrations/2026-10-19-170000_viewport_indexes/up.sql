@@ -0,0 +1,3 @@
+-- Your SQL goes here
+CREATE INDEX current_location_latitude_longitude ON current_location (latitude, longitude);
+CREATE INDEX place_location_latitude_longitude ON place_location (latitude, longitude);
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP TRIGGER place_location_cascade_aliases;
+DROP TRIGGER place_location_rename_aliases;
+DROP TABLE place_aliases;
@@ -0,0 +1,18 @@
+-- Your SQL goes here
+CREATE TABLE place_aliases (
+    alias TEXT NOT NULL PRIMARY KEY COLLATE NOCASE,
+    busid TEXT NOT NULL
+);
+CREATE INDEX place_aliases_busid ON place_aliases (busid);
+
+CREATE TRIGGER place_location_rename_aliases
+AFTER UPDATE OF busid ON place_location
+BEGIN
+    UPDATE place_aliases SET busid = NEW.busid WHERE busid = OLD.busid;
+END;
+
+CREATE TRIGGER place_location_cascade_aliases
+AFTER DELETE ON place_location
+BEGIN
+    DELETE FROM place_aliases WHERE busid = OLD.busid;
+END;
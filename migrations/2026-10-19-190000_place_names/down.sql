@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP TRIGGER place_location_cascade_names;
+DROP TRIGGER place_location_rename_names;
+DROP TABLE place_names;
@@ -0,0 +1,19 @@
+-- Your SQL goes here
+CREATE TABLE place_names (
+    busid TEXT NOT NULL,
+    lang TEXT NOT NULL COLLATE NOCASE,
+    name TEXT NOT NULL,
+    PRIMARY KEY (busid, lang)
+);
+
+CREATE TRIGGER place_location_rename_names
+AFTER UPDATE OF busid ON place_location
+BEGIN
+    UPDATE place_names SET busid = NEW.busid WHERE busid = OLD.busid;
+END;
+
+CREATE TRIGGER place_location_cascade_names
+AFTER DELETE ON place_location
+BEGIN
+    DELETE FROM place_names WHERE busid = OLD.busid;
+END;
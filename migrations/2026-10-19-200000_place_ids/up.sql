@@ -0,0 +1,66 @@
+-- Your SQL goes here
+-- Places used their name as id. The name moves to its own column and every
+-- place gets a generated id, which is rewritten into the stop lists of
+-- `routes` and into `busses`. Aliases and localized names follow through the
+-- rename triggers on `place_location`.
+ALTER TABLE place_location ADD COLUMN name TEXT NOT NULL DEFAULT '';
+UPDATE place_location SET name = busid;
+
+CREATE TEMP TABLE place_ids (old TEXT NOT NULL PRIMARY KEY, new TEXT NOT NULL UNIQUE);
+INSERT INTO place_ids (old, new)
+SELECT busid, printf('p%08x', rowid) FROM place_location;
+
+CREATE TEMP TABLE route_stops AS
+WITH RECURSIVE split (routeid, list, pos, stop, rest) AS (
+    SELECT routeid, 'outbound', 0, NULL, placeid || '|' FROM routes
+    UNION ALL
+    SELECT routeid, 'inbound', 0, NULL, inbound || '|' FROM routes WHERE inbound IS NOT NULL
+    UNION ALL
+    SELECT routeid, list, pos + 1,
+           substr(rest, 1, instr(rest, '|') - 1),
+           substr(rest, instr(rest, '|') + 1)
+    FROM split WHERE rest <> ''
+)
+SELECT routeid, list, pos, COALESCE((SELECT new FROM place_ids WHERE old = stop), stop) AS stop
+FROM split WHERE pos > 0;
+
+-- group_concat does not promise any order, so the lists are joined back up
+-- one stop at a time, following `pos`.
+CREATE TEMP TABLE route_lists AS
+WITH RECURSIVE joined (routeid, list, pos, stops) AS (
+    SELECT routeid, list, pos, stop FROM route_stops WHERE pos = 1
+    UNION ALL
+    SELECT joined.routeid, joined.list, route_stops.pos, joined.stops || '|' || route_stops.stop
+    FROM joined JOIN route_stops
+        ON route_stops.routeid = joined.routeid
+        AND route_stops.list = joined.list
+        AND route_stops.pos = joined.pos + 1
+)
+SELECT routeid, list, stops FROM joined
+WHERE NOT EXISTS (
+    SELECT 1 FROM route_stops
+    WHERE route_stops.routeid = joined.routeid
+        AND route_stops.list = joined.list
+        AND route_stops.pos = joined.pos + 1
+);
+
+UPDATE routes SET placeid = (
+    SELECT stops FROM route_lists
+    WHERE route_lists.routeid = routes.routeid AND list = 'outbound'
+);
+UPDATE routes SET inbound = (
+    SELECT stops FROM route_lists
+    WHERE route_lists.routeid = routes.routeid AND list = 'inbound'
+)
+WHERE inbound IS NOT NULL;
+
+UPDATE busses SET placeid = (SELECT new FROM place_ids WHERE old = busses.placeid)
+WHERE placeid IN (SELECT old FROM place_ids);
+
+UPDATE place_location SET busid = (SELECT new FROM place_ids WHERE old = place_location.busid);
+
+DROP TABLE route_lists;
+DROP TABLE route_stops;
+DROP TABLE place_ids;
+
+CREATE INDEX place_location_name ON place_location (name);
@@ -0,0 +1,7 @@
+-- This file should undo anything in `up.sql`
+-- Rows still waiting to be purged would come back to life, so they go now.
+DELETE FROM routes WHERE deleted_at IS NOT NULL;
+DELETE FROM place_location WHERE deleted_at IS NOT NULL;
+
+ALTER TABLE routes DROP COLUMN deleted_at;
+ALTER TABLE place_location DROP COLUMN deleted_at;
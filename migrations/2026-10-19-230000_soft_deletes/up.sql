@@ -0,0 +1,4 @@
+-- Your SQL goes here
+-- Unix seconds a place or route was deleted at; NULL while it is live.
+ALTER TABLE place_location ADD COLUMN deleted_at BIGINT;
+ALTER TABLE routes ADD COLUMN deleted_at BIGINT;
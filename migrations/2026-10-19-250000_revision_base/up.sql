@@ -0,0 +1,6 @@
+-- Your SQL goes here
+-- A draft keeps the live network it started from as JSON, so that a publish
+-- can tell whether the live network was edited since. The buses listed at
+-- each place follow from the routes, so revisions no longer hold their own.
+ALTER TABLE revisions ADD COLUMN base TEXT;
+DROP TABLE revision_busses;
@@ -0,0 +1,15 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE place_location;
+DROP FUNCTION place_location_rename();
+DROP FUNCTION place_location_cascade();
+DROP FUNCTION place_location_restrict();
+DROP TABLE place_names;
+DROP TABLE place_aliases;
+DROP TABLE busses;
+DROP TABLE vehicle_assignments;
+DROP TABLE route_shapes;
+DROP TABLE routes;
+DROP TABLE rejected_locations;
+DROP TABLE location_history;
+DROP TABLE current_location;
+DROP FUNCTION instr(TEXT, TEXT);
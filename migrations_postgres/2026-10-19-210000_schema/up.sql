@@ -0,0 +1,155 @@
+-- Your SQL goes here
+-- The SQLite schema as it stands after `migrations/`, for a fresh PostgreSQL
+-- database. `place_location` and `current_location` also carry a PostGIS
+-- point kept in step with their latitude and longitude.
+CREATE EXTENSION IF NOT EXISTS postgis;
+
+-- Same as SQLite's instr(): 1-based position of `needle`, 0 when absent.
+CREATE FUNCTION instr(haystack TEXT, needle TEXT) RETURNS INTEGER
+    AS $$ SELECT strpos(haystack, needle) $$
+    LANGUAGE SQL IMMUTABLE;
+
+CREATE TABLE current_location (
+    busid TEXT NOT NULL PRIMARY KEY,
+    latitude REAL NOT NULL,
+    longitude REAL NOT NULL,
+    recorded_at BIGINT NOT NULL DEFAULT 0,
+    speed REAL,
+    heading REAL,
+    accuracy REAL,
+    altitude REAL,
+    odometer DOUBLE PRECISION,
+    snapped_latitude REAL,
+    snapped_longitude REAL,
+    distance_along REAL,
+    direction TEXT,
+    geom geometry(Point, 4326) GENERATED ALWAYS AS
+        (ST_SetSRID(ST_MakePoint(longitude, latitude), 4326)) STORED
+);
+CREATE INDEX current_location_geom ON current_location USING GIST (geom);
+
+CREATE TABLE location_history (
+    id SERIAL PRIMARY KEY,
+    busid TEXT NOT NULL,
+    latitude REAL NOT NULL,
+    longitude REAL NOT NULL,
+    recorded_at BIGINT NOT NULL,
+    speed REAL,
+    heading REAL,
+    accuracy REAL,
+    altitude REAL,
+    odometer DOUBLE PRECISION,
+    snapped_latitude REAL,
+    snapped_longitude REAL,
+    distance_along REAL,
+    direction TEXT,
+    routeid TEXT
+);
+CREATE INDEX location_history_busid_recorded_at ON location_history (busid, recorded_at);
+
+CREATE TABLE rejected_locations (
+    id SERIAL PRIMARY KEY,
+    busid TEXT NOT NULL,
+    latitude REAL NOT NULL,
+    longitude REAL NOT NULL,
+    recorded_at BIGINT NOT NULL,
+    accuracy REAL,
+    reason TEXT NOT NULL,
+    received_at BIGINT NOT NULL
+);
+CREATE INDEX rejected_locations_busid ON rejected_locations (busid);
+
+CREATE TABLE routes (
+    routeid TEXT NOT NULL PRIMARY KEY,
+    placeid TEXT NOT NULL,
+    inbound TEXT
+);
+
+CREATE TABLE route_shapes (
+    routeid TEXT NOT NULL PRIMARY KEY,
+    shape TEXT NOT NULL
+);
+
+CREATE TABLE vehicle_assignments (
+    id SERIAL PRIMARY KEY,
+    busid TEXT NOT NULL,
+    routeid TEXT NOT NULL,
+    valid_from BIGINT NOT NULL,
+    valid_to BIGINT
+);
+CREATE INDEX vehicle_assignments_busid ON vehicle_assignments (busid, valid_to);
+CREATE INDEX vehicle_assignments_routeid ON vehicle_assignments (routeid, valid_to);
+
+CREATE TABLE busses (
+    placeid TEXT NOT NULL PRIMARY KEY,
+    busid TEXT NOT NULL
+);
+
+CREATE TABLE place_location (
+    busid TEXT NOT NULL PRIMARY KEY,
+    latitude REAL NOT NULL,
+    longitude REAL NOT NULL,
+    name TEXT NOT NULL DEFAULT '',
+    geom geometry(Point, 4326) GENERATED ALWAYS AS
+        (ST_SetSRID(ST_MakePoint(longitude, latitude), 4326)) STORED
+);
+CREATE INDEX place_location_name ON place_location (name);
+CREATE INDEX place_location_geom ON place_location USING GIST (geom);
+
+CREATE TABLE place_aliases (
+    alias TEXT NOT NULL PRIMARY KEY,
+    busid TEXT NOT NULL
+);
+CREATE UNIQUE INDEX place_aliases_lower_alias ON place_aliases (lower(alias));
+CREATE INDEX place_aliases_busid ON place_aliases (busid);
+
+CREATE TABLE place_names (
+    busid TEXT NOT NULL,
+    lang TEXT NOT NULL,
+    name TEXT NOT NULL,
+    PRIMARY KEY (busid, lang)
+);
+
+-- Stop lists in `routes` are `|`-joined place ids, which a FOREIGN KEY clause
+-- cannot express, so the reference is enforced with triggers instead.
+CREATE FUNCTION place_location_restrict() RETURNS TRIGGER AS $$
+BEGIN
+    IF EXISTS (
+        SELECT 1 FROM routes
+        WHERE instr('|' || placeid || '|', '|' || OLD.busid || '|') > 0
+           OR instr('|' || COALESCE(inbound, '') || '|', '|' || OLD.busid || '|') > 0
+    ) THEN
+        RAISE foreign_key_violation USING MESSAGE = 'FOREIGN KEY constraint failed';
+    END IF;
+    RETURN OLD;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER place_location_restrict
+BEFORE DELETE ON place_location
+FOR EACH ROW EXECUTE FUNCTION place_location_restrict();
+
+CREATE FUNCTION place_location_cascade() RETURNS TRIGGER AS $$
+BEGIN
+    DELETE FROM busses WHERE placeid = OLD.busid;
+    DELETE FROM place_aliases WHERE busid = OLD.busid;
+    DELETE FROM place_names WHERE busid = OLD.busid;
+    RETURN OLD;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER place_location_cascade
+AFTER DELETE ON place_location
+FOR EACH ROW EXECUTE FUNCTION place_location_cascade();
+
+CREATE FUNCTION place_location_rename() RETURNS TRIGGER AS $$
+BEGIN
+    UPDATE place_aliases SET busid = NEW.busid WHERE busid = OLD.busid;
+    UPDATE place_names SET busid = NEW.busid WHERE busid = OLD.busid;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER place_location_rename
+AFTER UPDATE OF busid ON place_location
+FOR EACH ROW EXECUTE FUNCTION place_location_rename();
@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE revision_busses;
+DROP TABLE revision_routes;
+DROP TABLE revision_places;
+DROP TABLE revisions;
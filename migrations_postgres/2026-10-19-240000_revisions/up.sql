@@ -0,0 +1,36 @@
+-- Your SQL goes here
+-- Each revision holds a full copy of the network. A draft is edited freely;
+-- publishing copies it over `place_location`, `routes` and `busses`, and the
+-- revision it replaces is kept as `retired` so it can be rolled back to.
+CREATE TABLE revisions (
+    id SERIAL PRIMARY KEY,
+    state TEXT NOT NULL DEFAULT 'draft',
+    note TEXT NOT NULL DEFAULT '',
+    created_at BIGINT NOT NULL,
+    published_at BIGINT
+);
+CREATE INDEX revisions_state ON revisions (state);
+
+CREATE TABLE revision_places (
+    revision INTEGER NOT NULL,
+    busid TEXT NOT NULL,
+    latitude REAL NOT NULL,
+    longitude REAL NOT NULL,
+    name TEXT NOT NULL,
+    PRIMARY KEY (revision, busid)
+);
+
+CREATE TABLE revision_routes (
+    revision INTEGER NOT NULL,
+    routeid TEXT NOT NULL,
+    placeid TEXT NOT NULL,
+    inbound TEXT,
+    PRIMARY KEY (revision, routeid)
+);
+
+CREATE TABLE revision_busses (
+    revision INTEGER NOT NULL,
+    placeid TEXT NOT NULL,
+    busid TEXT NOT NULL,
+    PRIMARY KEY (revision, placeid)
+);
@@ -0,0 +1,8 @@
+-- This file should undo anything in `up.sql`
+CREATE TABLE revision_busses (
+    revision INTEGER NOT NULL,
+    placeid TEXT NOT NULL,
+    busid TEXT NOT NULL,
+    PRIMARY KEY (revision, placeid)
+);
+ALTER TABLE revisions DROP COLUMN base;
@@ -0,0 +1,4 @@
+-- Your SQL goes here
+-- The Kalman filter's own estimate of how far off a smoothed position may
+-- be, kept apart from the accuracy the tracker reported.
+ALTER TABLE current_location ADD COLUMN estimated_accuracy REAL;
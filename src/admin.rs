@@ -0,0 +1,88 @@
+//! The admin credential. Everything under `/admin`, and every view that
+//! includes deleted entries, takes `Authorization: Bearer <token>` with the
+//! token from `[admin]`; the server refuses to start without one.
+
+use rocket::fairing::AdHoc;
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::serde::Deserialize;
+
+/// The `[admin]` section of `Rocket.toml`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct AdminConfig {
+    pub token: String,
+}
+
+/// A request guard that only lets the admin token through, failing with 401
+/// otherwise.
+#[derive(Debug, Clone, Copy)]
+pub struct Admin;
+
+/// Compares in time that depends on the length only, so a wrong token does
+/// not tell how much of it was right.
+fn same(expected: &str, given: &str) -> bool {
+    expected.len() == given.len()
+        && expected
+            .bytes()
+            .zip(given.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Admin {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, ()> {
+        let config = match req.rocket().state::<AdminConfig>() {
+            Some(config) => config,
+            None => return Outcome::Failure((Status::InternalServerError, ())),
+        };
+        let token = req
+            .headers()
+            .get_one("Authorization")
+            .and_then(|value| value.strip_prefix("Bearer "));
+        match token {
+            Some(token) if same(&config.token, token.trim()) => Outcome::Success(Admin),
+            _ => Outcome::Failure((Status::Unauthorized, ())),
+        }
+    }
+}
+
+/// `?include_deleted=true`, which only the admin may ask for: a request guard
+/// failing with 401 when anyone else does.
+#[derive(Debug, Clone, Copy)]
+pub struct IncludeDeleted(pub bool);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for IncludeDeleted {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, ()> {
+        let wanted = req
+            .query_value::<bool>("include_deleted")
+            .and_then(Result::ok)
+            .unwrap_or(false);
+        if !wanted {
+            return Outcome::Success(IncludeDeleted(false));
+        }
+        req.guard::<Admin>().await.map(|_| IncludeDeleted(true))
+    }
+}
+
+pub fn admin() -> AdHoc {
+    AdHoc::try_on_ignite("Admin token", |rocket| async {
+        match rocket.figment().extract_inner::<AdminConfig>("admin") {
+            Ok(config) if !config.token.is_empty() => Ok(rocket.manage(config)),
+            Ok(_) => {
+                error!("[admin] token is empty");
+                Err(rocket)
+            }
+            Err(e) => {
+                error!("[admin] token: {}", e);
+                Err(rocket)
+            }
+        }
+    })
+}
@@ -0,0 +1,252 @@
+//! A trail of every change made through the API: who made it, what it was,
+//! and the entity before and after as JSON. Writes record their change in the
+//! same transaction as the change itself. Position fixes from trackers are
+//! left out; `location_history` keeps those.
+
+use std::convert::Infallible;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rocket::fairing::AdHoc;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::response::{Debug, Responder};
+use rocket::serde::json::{self, Value};
+use rocket::serde::Serialize;
+use rocket::{http, Build, Rocket};
+
+use self::diesel::prelude::*;
+use rocket_cors::{AllowedHeaders, AllowedOrigins, CorsOptions, Method};
+use rocket_sync_db_pools::diesel;
+
+use crate::admin::Admin;
+use crate::db::{self, Db};
+use crate::paging::Paging;
+
+type Result<T, E = Debug<diesel::result::Error>> = std::result::Result<T, E>;
+
+table! {
+    audit_log (id) {
+        id -> Integer,
+        at -> BigInt,
+        actor -> Text,
+        action -> Text,
+        entity -> Text,
+        entity_id -> Text,
+        before -> Nullable<Text>,
+        after -> Nullable<Text>,
+    }
+}
+
+fn core_options() -> CorsOptions {
+    rocket_cors::CorsOptions {
+        allowed_origins: AllowedOrigins::all(),
+        allowed_methods: vec![http::Method::Get, http::Method::Options]
+            .into_iter()
+            .map(Method)
+            .collect(),
+        allowed_headers: AllowedHeaders::all(),
+        allow_credentials: true,
+        fairing_route_base: "/".to_owned(),
+        max_age: Some(42),
+        ..Default::default()
+    }
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() as i64)
+}
+
+/// Who made a request: the client's IP address, after the name in the
+/// `X-Actor` header an admin tool sets as `name@ip`. The name is only what
+/// the client claims; the address is what the server saw.
+#[derive(Debug, Clone)]
+pub struct Actor(String);
+
+impl Actor {
+    /// The server itself, for changes made by background jobs.
+    pub fn system() -> Actor {
+        Actor("system".to_owned())
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Actor {
+    type Error = Infallible;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Infallible> {
+        let ip = req
+            .client_ip()
+            .map_or_else(|| "unknown".to_owned(), |ip| ip.to_string());
+        let actor = match req.headers().get_one("X-Actor") {
+            Some(name) => format!("{name}@{ip}"),
+            None => ip,
+        };
+        Outcome::Success(Actor(actor))
+    }
+}
+
+/// One change to record, e.g.
+/// `Change::new("delete", "route", &id).before(&route).record(conn, &actor)`.
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = audit_log)]
+pub struct Change {
+    at: i64,
+    actor: String,
+    action: String,
+    entity: String,
+    entity_id: String,
+    before: Option<String>,
+    after: Option<String>,
+}
+
+/// JSON for a snapshot; `None` and unit serialize to no snapshot at all.
+fn snapshot(value: &impl Serialize) -> Option<String> {
+    json::to_value(value)
+        .ok()
+        .filter(|value| !value.is_null())
+        .map(|value| value.to_string())
+}
+
+impl Change {
+    pub fn new(action: &str, entity: &str, entity_id: &str) -> Change {
+        Change {
+            at: now(),
+            actor: String::new(),
+            action: action.to_owned(),
+            entity: entity.to_owned(),
+            entity_id: entity_id.to_owned(),
+            before: None,
+            after: None,
+        }
+    }
+
+    pub fn before(self, value: &impl Serialize) -> Change {
+        Change {
+            before: snapshot(value),
+            ..self
+        }
+    }
+
+    pub fn after(self, value: &impl Serialize) -> Change {
+        Change {
+            after: snapshot(value),
+            ..self
+        }
+    }
+
+    pub fn record(self, conn: &mut db::Connection, actor: &Actor) -> QueryResult<()> {
+        diesel::insert_into(audit_log::table)
+            .values(Change {
+                actor: actor.0.clone(),
+                ..self
+            })
+            .execute(conn)
+            .map(|_| ())
+    }
+}
+
+/// A recorded change as served by `/admin/audit`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(crate = "rocket::serde")]
+struct Entry {
+    id: i32,
+    at: i64,
+    actor: String,
+    action: String,
+    entity: String,
+    entity_id: String,
+    before: Option<Value>,
+    after: Option<Value>,
+}
+
+type Row = (
+    i32,
+    i64,
+    String,
+    String,
+    String,
+    String,
+    Option<String>,
+    Option<String>,
+);
+
+impl From<Row> for Entry {
+    fn from((id, at, actor, action, entity, entity_id, before, after): Row) -> Self {
+        let parse = |text: Option<String>| text.and_then(|text| json::from_str(&text).ok());
+        Entry {
+            id,
+            at,
+            actor,
+            action,
+            entity,
+            entity_id,
+            before: parse(before),
+            after: parse(after),
+        }
+    }
+}
+
+/// Recorded changes, newest first, optionally only those to `?entity=` (a
+/// kind such as `route`, or one entity as `route/<id>`) and those made at or
+/// after `?since=` (unix seconds). Paged with `?limit=` and `?offset=`.
+#[get("/audit?<entity>&<since>&<paging..>")]
+async fn list_audit<'r, 'o: 'r>(
+    _admin: Admin,
+    db: Db,
+    entity: Option<String>,
+    since: Option<i64>,
+    paging: Paging,
+) -> Result<impl Responder<'r, 'o>> {
+    let rows: Vec<Row> = db
+        .run(move |conn| {
+            let mut query = audit_log::table.into_boxed();
+            if let Some(entity) = entity {
+                query = match entity.split_once('/') {
+                    Some((kind, id)) => query
+                        .filter(audit_log::entity.eq(kind.to_owned()))
+                        .filter(audit_log::entity_id.eq(id.to_owned())),
+                    None => query.filter(audit_log::entity.eq(entity)),
+                };
+            }
+            if let Some(since) = since {
+                query = query.filter(audit_log::at.ge(since));
+            }
+            query
+                .order(audit_log::id.desc())
+                .limit(paging.fetch())
+                .offset(paging.offset())
+                .load(conn)
+        })
+        .await?;
+
+    let out = paging.page(rows.into_iter().map(Entry::from).collect());
+    let options = match core_options().to_cors() {
+        Ok(a) => a,
+        Err(a) => return Ok(Err(a)),
+    };
+    Ok(options.respond_owned(move |guard| guard.responder(out)))
+}
+
+async fn run_migrations(rocket: Rocket<Build>) -> Rocket<Build> {
+    use diesel_migrations::MigrationHarness;
+
+    Db::get_one(&rocket)
+        .await
+        .expect("database connection")
+        .run(|conn| {
+            conn.run_pending_migrations(db::MIGRATIONS)
+                .expect("diesel migrations");
+        })
+        .await;
+
+    rocket
+}
+
+pub fn audit_data() -> AdHoc {
+    AdHoc::on_ignite("Audit log", |rocket| async {
+        rocket
+            .attach(AdHoc::on_ignite("Diesel Migrations", run_migrations))
+            .mount("/admin", routes![list_audit])
+    })
+}
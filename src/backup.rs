@@ -0,0 +1,1011 @@
+//! Backups of the database. A snapshot is a consistent copy of the SQLite
+//! file, taken with SQLite's online backup API while the server keeps
+//! serving, and written to the `[backup]` directory; restoring copies one
+//! back over the live database the same way. The JSON dump holds the places
+//! with their names and aliases, the routes, `busses` and the buses'
+//! positions, for moving data between environments, PostgreSQL ones
+//! included. Other tables, such as route shapes and assignments, are left
+//! as they are.
+//!
+//! Restores and imports through the API wait until no other write is
+//! running and keep all writes out until they are done. The same operations
+//! run from the command line, where restores and imports can't queue with a
+//! running server's writes; stop the server for those. See [`command`].
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rocket::fairing::AdHoc;
+use rocket::figment::{self, Figment};
+use rocket::response::{status::Created, Debug, Responder};
+use rocket::serde::{json, json::Json, Deserialize, Serialize};
+use rocket::tokio::task::spawn_blocking;
+use rocket::{http, Build, Rocket, State};
+
+use self::diesel::prelude::*;
+use rocket_cors::{AllowedHeaders, AllowedOrigins, CorsOptions, Method};
+use rocket_sync_db_pools::diesel;
+
+use crate::admin::Admin;
+use crate::audit::{Actor, Change};
+use crate::bus::{self, Positions};
+use crate::config;
+use crate::db::{self, Db};
+
+type Result<T, E = Debug<Error>> = std::result::Result<T, E>;
+
+const USAGE: &str = "usage: bus-server [snapshot | snapshots | prune [<keep>] | restore <name> \
+                     | export [<file>] | import <file>]";
+
+table! {
+    place_location (busid) {
+        busid -> Text,
+        latitude -> Float,
+        longitude -> Float,
+        name -> Text,
+        deleted_at -> Nullable<BigInt>,
+    }
+}
+
+table! {
+    place_aliases (alias) {
+        alias -> Text,
+        busid -> Text,
+    }
+}
+
+table! {
+    place_names (busid, lang) {
+        busid -> Text,
+        lang -> Text,
+        name -> Text,
+    }
+}
+
+table! {
+    routes (routeid) {
+        routeid -> Text,
+        placeid -> Text,
+        inbound -> Nullable<Text>,
+        deleted_at -> Nullable<BigInt>,
+    }
+}
+
+table! {
+    busses (placeid) {
+        placeid -> Text,
+        busid -> Text,
+    }
+}
+
+table! {
+    current_location (busid) {
+        busid -> Text,
+        latitude -> Float,
+        longitude -> Float,
+        recorded_at -> BigInt,
+        speed -> Nullable<Float>,
+        heading -> Nullable<Float>,
+        accuracy -> Nullable<Float>,
+        altitude -> Nullable<Float>,
+        odometer -> Nullable<Double>,
+        snapped_latitude -> Nullable<Float>,
+        snapped_longitude -> Nullable<Float>,
+        distance_along -> Nullable<Float>,
+        direction -> Nullable<Text>,
+        estimated_accuracy -> Nullable<Float>,
+    }
+}
+
+fn core_options() -> CorsOptions {
+    rocket_cors::CorsOptions {
+        allowed_origins: AllowedOrigins::all(),
+        allowed_methods: vec![
+            http::Method::Get,
+            http::Method::Post,
+            http::Method::Options,
+            http::Method::Delete,
+        ]
+        .into_iter()
+        .map(Method)
+        .collect(),
+        allowed_headers: AllowedHeaders::all(),
+        allow_credentials: true,
+        fairing_route_base: "/".to_owned(),
+        max_age: Some(42),
+        ..Default::default()
+    }
+}
+
+/// What can go wrong taking, restoring or moving a backup.
+#[derive(Debug)]
+enum Error {
+    Db(diesel::result::Error),
+    Io(io::Error),
+    Json(json::serde_json::Error),
+    /// From SQLite's backup API, or from migrating a restored database.
+    Backup(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Db(error) => write!(f, "database: {error}"),
+            Error::Io(error) => write!(f, "{error}"),
+            Error::Json(error) => write!(f, "json: {error}"),
+            Error::Backup(message) => write!(f, "backup: {message}"),
+        }
+    }
+}
+
+impl From<diesel::result::Error> for Error {
+    fn from(error: diesel::result::Error) -> Self {
+        Error::Db(error)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+impl From<json::serde_json::Error> for Error {
+    fn from(error: json::serde_json::Error) -> Self {
+        Error::Json(error)
+    }
+}
+
+/// The `[backup]` section of `Rocket.toml`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct BackupConfig {
+    /// Directory snapshots are written to.
+    pub dir: PathBuf,
+    /// Snapshots a prune keeps when not told otherwise.
+    pub keep: usize,
+}
+
+impl Default for BackupConfig {
+    fn default() -> Self {
+        BackupConfig {
+            dir: PathBuf::from("db/backups"),
+            keep: 10,
+        }
+    }
+}
+
+/// A snapshot on disk, named `db-<unix milliseconds>.sqlite`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(crate = "rocket::serde")]
+struct Snapshot {
+    name: String,
+    /// Unix seconds.
+    created_at: i64,
+    /// Bytes.
+    size: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(crate = "rocket::serde")]
+struct Restored {
+    restored: Snapshot,
+    /// Taken just before, so that the restore can be undone.
+    saved: Snapshot,
+}
+
+/// When a snapshot called `name` was taken, in unix milliseconds; `None` for
+/// names that aren't snapshots, which keeps requests out of other files.
+fn taken_at(name: &str) -> Option<i64> {
+    let millis = name.strip_prefix("db-")?.strip_suffix(".sqlite")?;
+    if millis.is_empty() || !millis.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    millis.parse().ok()
+}
+
+/// Runs file work on a blocking thread, so that it holds neither the async
+/// runtime nor a pooled connection while it waits on the disk.
+async fn blocking<T: Send + 'static>(
+    work: impl FnOnce() -> Result<T, Error> + Send + 'static,
+) -> Result<T, Error> {
+    spawn_blocking(work)
+        .await
+        .map_err(|error| Error::Backup(error.to_string()))?
+}
+
+/// The database and where its snapshots go. Managed state.
+#[derive(Debug, Clone)]
+struct Backups {
+    url: String,
+    config: BackupConfig,
+}
+
+impl Backups {
+    fn new(figment: &Figment) -> Result<Self, figment::Error> {
+        Ok(Backups {
+            url: figment
+                .extract_inner("databases.diesel.url")
+                .unwrap_or_else(|_| "db/diesel/db.sqlite".to_owned()),
+            config: config::section(figment, "backup")?,
+        })
+    }
+
+    fn path(&self, name: &str) -> Option<PathBuf> {
+        taken_at(name).map(|_| self.config.dir.join(name))
+    }
+
+    fn describe(&self, name: String) -> Result<Snapshot, Error> {
+        let created_at = taken_at(&name).unwrap_or(0) / 1000;
+        let size = fs::metadata(self.config.dir.join(&name))?.len();
+        Ok(Snapshot {
+            name,
+            created_at,
+            size,
+        })
+    }
+
+    /// Snapshots, newest first.
+    fn list(&self) -> Result<Vec<Snapshot>, Error> {
+        let entries = match fs::read_dir(&self.config.dir) {
+            Ok(entries) => entries,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(error) => return Err(error.into()),
+        };
+        let mut names = vec![];
+        for entry in entries {
+            let name = entry?.file_name().to_string_lossy().into_owned();
+            if taken_at(&name).is_some() {
+                names.push(name);
+            }
+        }
+        names.sort_by_key(|name| std::cmp::Reverse(taken_at(name)));
+        names.into_iter().map(|name| self.describe(name)).collect()
+    }
+
+    fn take(&self) -> Result<Snapshot, Error> {
+        fs::create_dir_all(&self.config.dir)?;
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_millis());
+        let name = format!("db-{millis}.sqlite");
+        copy(Path::new(&self.url), &self.config.dir.join(&name))?;
+        self.describe(name)
+    }
+
+    /// Deletes a snapshot; `None` when there is no such snapshot.
+    fn remove(&self, name: &str) -> Result<Option<Snapshot>, Error> {
+        let path = match self.path(name) {
+            Some(path) if path.is_file() => path,
+            _ => return Ok(None),
+        };
+        let snapshot = self.describe(name.to_owned())?;
+        fs::remove_file(path)?;
+        Ok(Some(snapshot))
+    }
+
+    /// Deletes all but the newest `keep` snapshots and returns the deleted.
+    fn prune(&self, keep: usize) -> Result<Vec<Snapshot>, Error> {
+        let pruned: Vec<Snapshot> = self.list()?.into_iter().skip(keep).collect();
+        for snapshot in &pruned {
+            fs::remove_file(self.config.dir.join(&snapshot.name))?;
+        }
+        Ok(pruned)
+    }
+
+    /// Copies a snapshot over the live database, after taking one of the
+    /// database as it was. `None` when there is no such snapshot.
+    fn restore(&self, name: &str) -> Result<Option<Restored>, Error> {
+        let path = match self.path(name) {
+            Some(path) if path.is_file() => path,
+            _ => return Ok(None),
+        };
+        let restored = self.describe(name.to_owned())?;
+        let saved = self.take()?;
+        copy(&path, Path::new(&self.url))?;
+        Ok(Some(Restored { restored, saved }))
+    }
+
+    fn connect(&self) -> Result<db::Connection, Error> {
+        db::Connection::establish(&self.url).map_err(|error| Error::Backup(error.to_string()))
+    }
+}
+
+/// Copies the database at `from` into `to` with SQLite's backup API, in a
+/// single step so the copy is of one consistent state of `from`. Connections
+/// to either file can stay open meanwhile.
+#[cfg(not(feature = "postgres"))]
+fn copy(from: &Path, to: &Path) -> Result<(), Error> {
+    use std::ffi::{CStr, CString};
+    use std::os::raw::{c_char, c_int};
+    use std::ptr;
+    use std::thread;
+    use std::time::Duration;
+
+    use libsqlite3_sys as ffi;
+
+    /// Attempts at a step the other connections keep locked out.
+    const ATTEMPTS: usize = 50;
+
+    struct Handle(*mut ffi::sqlite3);
+
+    impl Handle {
+        fn message(&self) -> String {
+            // SAFETY: `sqlite3_errmsg` takes any handle `sqlite3_open_v2` gave,
+            // null included, and the message outlives this borrow.
+            unsafe { CStr::from_ptr(ffi::sqlite3_errmsg(self.0)) }
+                .to_string_lossy()
+                .into_owned()
+        }
+    }
+
+    impl Drop for Handle {
+        fn drop(&mut self) {
+            // SAFETY: closing null is a no-op, and nothing else closes it.
+            unsafe { ffi::sqlite3_close(self.0) };
+        }
+    }
+
+    fn open(path: &Path, flags: c_int) -> Result<Handle, Error> {
+        let name = CString::new(path.to_string_lossy().into_owned())
+            .map_err(|_| Error::Backup("database path contains a NUL byte".to_owned()))?;
+        let mut db = ptr::null_mut();
+        // SAFETY: `name` is NUL-terminated and outlives the call.
+        let code = unsafe { ffi::sqlite3_open_v2(name.as_ptr(), &mut db, flags, ptr::null()) };
+        let handle = Handle(db);
+        if code != ffi::SQLITE_OK {
+            return Err(Error::Backup(handle.message()));
+        }
+        // SAFETY: the handle is open.
+        unsafe { ffi::sqlite3_busy_timeout(handle.0, 5000) };
+        Ok(handle)
+    }
+
+    let source = open(from, ffi::SQLITE_OPEN_READONLY)?;
+    let target = open(to, ffi::SQLITE_OPEN_READWRITE | ffi::SQLITE_OPEN_CREATE)?;
+    let main: *const c_char = b"main\0".as_ptr().cast();
+    // SAFETY: both handles are open and outlive the backup, which is
+    // finished before they are dropped; `sqlite3_errstr` returns static
+    // strings.
+    unsafe {
+        let backup = ffi::sqlite3_backup_init(target.0, main, source.0, main);
+        if backup.is_null() {
+            return Err(Error::Backup(target.message()));
+        }
+        let mut step = ffi::SQLITE_BUSY;
+        for _ in 0..ATTEMPTS {
+            step = ffi::sqlite3_backup_step(backup, -1);
+            match step {
+                ffi::SQLITE_BUSY | ffi::SQLITE_LOCKED => thread::sleep(Duration::from_millis(100)),
+                _ => break,
+            }
+        }
+        // Frees the backup; it fails only on errors the last step hit too,
+        // and a step still busy after every attempt isn't one of them.
+        let finish = ffi::sqlite3_backup_finish(backup);
+        if step != ffi::SQLITE_DONE {
+            let message = CStr::from_ptr(ffi::sqlite3_errstr(step)).to_string_lossy();
+            return Err(Error::Backup(format!("copy not finished: {message}")));
+        }
+        if finish != ffi::SQLITE_OK {
+            return Err(Error::Backup(target.message()));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(feature = "postgres")]
+fn copy(_: &Path, _: &Path) -> Result<(), Error> {
+    Err(Error::Backup(
+        "snapshots need the SQLite backend; use pg_dump on PostgreSQL".to_owned(),
+    ))
+}
+
+fn migrate(conn: &mut db::Connection) -> Result<(), Error> {
+    use diesel_migrations::MigrationHarness;
+
+    conn.run_pending_migrations(db::MIGRATIONS)
+        .map(drop)
+        .map_err(|error| Error::Backup(error.to_string()))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Insertable)]
+#[serde(crate = "rocket::serde")]
+#[diesel(table_name = place_location, treat_none_as_default_value = false)]
+struct Place {
+    busid: String,
+    latitude: f32,
+    longitude: f32,
+    name: String,
+    deleted_at: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Insertable)]
+#[serde(crate = "rocket::serde")]
+#[diesel(table_name = place_names)]
+struct PlaceName {
+    busid: String,
+    lang: String,
+    name: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Insertable)]
+#[serde(crate = "rocket::serde")]
+#[diesel(table_name = place_aliases)]
+struct PlaceAlias {
+    alias: String,
+    busid: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Insertable)]
+#[serde(crate = "rocket::serde")]
+#[diesel(table_name = routes, treat_none_as_default_value = false)]
+struct Route {
+    routeid: String,
+    placeid: String,
+    inbound: Option<String>,
+    deleted_at: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Insertable)]
+#[serde(crate = "rocket::serde")]
+#[diesel(table_name = busses)]
+struct Busses {
+    placeid: String,
+    busid: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Insertable)]
+#[serde(crate = "rocket::serde")]
+#[diesel(table_name = current_location, treat_none_as_default_value = false)]
+struct Position {
+    busid: String,
+    latitude: f32,
+    longitude: f32,
+    recorded_at: i64,
+    speed: Option<f32>,
+    heading: Option<f32>,
+    accuracy: Option<f32>,
+    altitude: Option<f32>,
+    odometer: Option<f64>,
+    snapped_latitude: Option<f32>,
+    snapped_longitude: Option<f32>,
+    distance_along: Option<f32>,
+    direction: Option<String>,
+    estimated_accuracy: Option<f32>,
+}
+
+/// Every row of the dumped tables, deleted places and routes included.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct Dump {
+    #[serde(default)]
+    place_location: Vec<Place>,
+    #[serde(default)]
+    place_names: Vec<PlaceName>,
+    #[serde(default)]
+    place_aliases: Vec<PlaceAlias>,
+    #[serde(default)]
+    routes: Vec<Route>,
+    #[serde(default)]
+    busses: Vec<Busses>,
+    #[serde(default)]
+    current_location: Vec<Position>,
+}
+
+/// Rows written by an import, by table.
+#[derive(Debug, Clone, Serialize)]
+#[serde(crate = "rocket::serde")]
+struct Imported {
+    place_location: usize,
+    place_names: usize,
+    place_aliases: usize,
+    routes: usize,
+    busses: usize,
+    current_location: usize,
+}
+
+/// Rows per `INSERT`; keeps the bind parameters under SQLite's limit.
+const CHUNK: usize = 50;
+
+fn export(conn: &mut db::Connection) -> QueryResult<Dump> {
+    Ok(Dump {
+        place_location: place_location::table
+            .order(place_location::busid)
+            .load(conn)?,
+        place_names: place_names::table
+            .order((place_names::busid, place_names::lang))
+            .load(conn)?,
+        place_aliases: place_aliases::table
+            .order(place_aliases::alias)
+            .load(conn)?,
+        routes: routes::table.order(routes::routeid).load(conn)?,
+        busses: busses::table.order(busses::placeid).load(conn)?,
+        current_location: current_location::table
+            .order(current_location::busid)
+            .load(conn)?,
+    })
+}
+
+/// Replaces the dumped tables with the rows of `dump`, in one transaction.
+/// Routes go first, as places can't be deleted while routes stop there.
+fn import(conn: &mut db::Connection, dump: &Dump, actor: &Actor) -> QueryResult<Imported> {
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        diesel::delete(routes::table).execute(conn)?;
+        diesel::delete(busses::table).execute(conn)?;
+        diesel::delete(place_names::table).execute(conn)?;
+        diesel::delete(place_aliases::table).execute(conn)?;
+        diesel::delete(place_location::table).execute(conn)?;
+        diesel::delete(current_location::table).execute(conn)?;
+        for rows in dump.place_location.chunks(CHUNK) {
+            diesel::insert_into(place_location::table)
+                .values(rows)
+                .execute(conn)?;
+        }
+        for rows in dump.place_names.chunks(CHUNK) {
+            diesel::insert_into(place_names::table)
+                .values(rows)
+                .execute(conn)?;
+        }
+        for rows in dump.place_aliases.chunks(CHUNK) {
+            diesel::insert_into(place_aliases::table)
+                .values(rows)
+                .execute(conn)?;
+        }
+        for rows in dump.routes.chunks(CHUNK) {
+            diesel::insert_into(routes::table)
+                .values(rows)
+                .execute(conn)?;
+        }
+        for rows in dump.busses.chunks(CHUNK) {
+            diesel::insert_into(busses::table)
+                .values(rows)
+                .execute(conn)?;
+        }
+        for rows in dump.current_location.chunks(CHUNK) {
+            diesel::insert_into(current_location::table)
+                .values(rows)
+                .execute(conn)?;
+        }
+        let imported = Imported {
+            place_location: dump.place_location.len(),
+            place_names: dump.place_names.len(),
+            place_aliases: dump.place_aliases.len(),
+            routes: dump.routes.len(),
+            busses: dump.busses.len(),
+            current_location: dump.current_location.len(),
+        };
+        Change::new("import", "backup", "*")
+            .after(&imported)
+            .record(conn, actor)?;
+        Ok(imported)
+    })
+}
+
+/// Runs a backup command given on the command line and returns the exit
+/// status:
+///
+///     bus-server snapshot          take a snapshot and print its name
+///     bus-server snapshots         list snapshots, newest first
+///     bus-server prune [<keep>]    delete all but the newest snapshots
+///     bus-server restore <name>    copy a snapshot over the database
+///     bus-server export [<file>]   write the JSON dump, to stdout by default
+///     bus-server import <file>     replace the dumped tables with a JSON dump
+pub fn command(args: &[String]) -> i32 {
+    let backups = match Backups::new(&rocket::Config::figment()) {
+        Ok(backups) => backups,
+        Err(error) => {
+            eprintln!("[backup]: {error}");
+            return 1;
+        }
+    };
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let done = match args.as_slice() {
+        ["snapshot"] => backups.take().map(|snapshot| println!("{}", snapshot.name)),
+        ["snapshots"] => backups.list().map(|snapshots| {
+            for snapshot in snapshots {
+                println!(
+                    "{}\t{}\t{}",
+                    snapshot.name, snapshot.created_at, snapshot.size
+                );
+            }
+        }),
+        ["prune"] => prune_command(&backups, backups.config.keep),
+        ["prune", keep] => match keep.parse() {
+            Ok(keep) => prune_command(&backups, keep),
+            Err(_) => {
+                eprintln!("{USAGE}");
+                return 2;
+            }
+        },
+        ["restore", name] => restore_command(&backups, name),
+        ["export"] => export_command(&backups, None),
+        ["export", file] => export_command(&backups, Some(Path::new(file))),
+        ["import", file] => import_command(&backups, Path::new(file)),
+        _ => {
+            eprintln!("{USAGE}");
+            return 2;
+        }
+    };
+    match done {
+        Ok(()) => 0,
+        Err(error) => {
+            eprintln!("{error}");
+            1
+        }
+    }
+}
+
+fn prune_command(backups: &Backups, keep: usize) -> Result<(), Error> {
+    for snapshot in backups.prune(keep)? {
+        println!("deleted {}", snapshot.name);
+    }
+    Ok(())
+}
+
+fn restore_command(backups: &Backups, name: &str) -> Result<(), Error> {
+    let restored = backups
+        .restore(name)?
+        .ok_or_else(|| Error::Backup(format!("no snapshot named {name}")))?;
+    let mut conn = backups.connect()?;
+    migrate(&mut conn)?;
+    Change::new("restore", "backup", name)
+        .after(&restored)
+        .record(&mut conn, &Actor::system())?;
+    println!(
+        "restored {}; the database before is in {}",
+        restored.restored.name, restored.saved.name
+    );
+    Ok(())
+}
+
+fn export_command(backups: &Backups, file: Option<&Path>) -> Result<(), Error> {
+    let dump = export(&mut backups.connect()?)?;
+    let text = json::to_pretty_string(&dump)?;
+    match file {
+        Some(file) => fs::write(file, text)?,
+        None => println!("{text}"),
+    }
+    Ok(())
+}
+
+fn import_command(backups: &Backups, file: &Path) -> Result<(), Error> {
+    let dump: Dump = json::from_str(&fs::read_to_string(file)?)?;
+    let imported = import(&mut backups.connect()?, &dump, &Actor::system())?;
+    println!(
+        "imported {} places, {} names, {} aliases, {} routes, {} busses entries and {} \
+         positions",
+        imported.place_location,
+        imported.place_names,
+        imported.place_aliases,
+        imported.routes,
+        imported.busses,
+        imported.current_location
+    );
+    Ok(())
+}
+
+/// Snapshots, newest first.
+#[get("/snapshots")]
+async fn list_snapshots<'r, 'o: 'r>(
+    _admin: Admin,
+    db: Db,
+    backups: &State<Backups>,
+) -> Result<impl Responder<'r, 'o>> {
+    let backups = backups.inner().clone();
+    let out = Json(db.run(move |_| backups.list()).await?);
+    let options = match core_options().to_cors() {
+        Ok(a) => a,
+        Err(a) => return Ok(Err(a)),
+    };
+    Ok(options.respond_owned(move |guard| guard.responder(out)))
+}
+
+/// Takes a snapshot of the database as it is now.
+#[post("/snapshots")]
+async fn snapshot_post<'r, 'o: 'r>(
+    _admin: Admin,
+    db: Db,
+    actor: Actor,
+    backups: &State<Backups>,
+) -> Result<impl Responder<'r, 'o>> {
+    let backups = backups.inner().clone();
+    let snapshot = blocking(move || backups.take()).await?;
+    let recorded = snapshot.clone();
+    db.write(move |conn| {
+        Change::new("snapshot", "backup", &recorded.name)
+            .after(&recorded)
+            .record(conn, &actor)
+            .map_err(Error::from)
+    })
+    .await?;
+    let out =
+        Created::new(format!("/admin/backups/snapshots/{}", snapshot.name)).body(Json(snapshot));
+    let options = match core_options().to_cors() {
+        Ok(a) => a,
+        Err(a) => return Ok(Err(a)),
+    };
+    Ok(options.respond_owned(move |guard| guard.responder(out)))
+}
+
+#[delete("/snapshots/<name>")]
+async fn delete_snapshot<'r, 'o: 'r>(
+    _admin: Admin,
+    db: Db,
+    actor: Actor,
+    backups: &State<Backups>,
+    name: String,
+) -> Result<impl Responder<'r, 'o>> {
+    let backups = backups.inner().clone();
+    let removed = {
+        let name = name.clone();
+        blocking(move || backups.remove(&name)).await?
+    };
+    let out = match removed {
+        Some(snapshot) => {
+            db.write(move |conn| {
+                Change::new("delete", "backup", &name)
+                    .before(&snapshot)
+                    .record(conn, &actor)
+                    .map_err(Error::from)
+            })
+            .await?;
+            Some(())
+        }
+        None => None,
+    };
+    let options = match core_options().to_cors() {
+        Ok(a) => a,
+        Err(a) => return Ok(Err(a)),
+    };
+    Ok(options.respond_owned(move |guard| guard.responder(out)))
+}
+
+/// Deletes all but the newest `?keep=` snapshots, `[backup] keep` by
+/// default, and answers with the deleted ones.
+#[post("/snapshots/prune?<keep>")]
+async fn prune_post<'r, 'o: 'r>(
+    _admin: Admin,
+    db: Db,
+    actor: Actor,
+    backups: &State<Backups>,
+    keep: Option<usize>,
+) -> Result<impl Responder<'r, 'o>> {
+    let backups = backups.inner().clone();
+    let pruned = blocking(move || backups.prune(keep.unwrap_or(backups.config.keep))).await?;
+    if !pruned.is_empty() {
+        let recorded = pruned.clone();
+        db.write(move |conn| {
+            Change::new("prune", "backup", "*")
+                .before(&recorded)
+                .record(conn, &actor)
+                .map_err(Error::from)
+        })
+        .await?;
+    }
+    let out = Json(pruned);
+    let options = match core_options().to_cors() {
+        Ok(a) => a,
+        Err(a) => return Ok(Err(a)),
+    };
+    Ok(options.respond_owned(move |guard| guard.responder(out)))
+}
+
+/// Copies a snapshot over the live database, after taking a snapshot of it
+/// so the restore can be undone, then migrates it if it is from an older
+/// build. Positions held in memory are replaced with the restored ones.
+/// Other writes wait until it is done.
+#[post("/snapshots/<name>/restore")]
+async fn restore_post<'r, 'o: 'r>(
+    _admin: Admin,
+    db: Db,
+    actor: Actor,
+    backups: &State<Backups>,
+    live: &State<Positions>,
+    name: String,
+) -> Result<impl Responder<'r, 'o>> {
+    let backups = backups.inner().clone();
+    let live = live.inner().clone();
+    let _turn = db.exclusive().await;
+    let restored = {
+        let name = name.clone();
+        blocking(move || backups.restore(&name)).await?
+    };
+    let out = match restored {
+        Some(restored) => {
+            db.run(move |conn| {
+                migrate(conn)?;
+                bus::reload(conn, &live)?;
+                Change::new("restore", "backup", &name)
+                    .after(&restored)
+                    .record(conn, &actor)?;
+                Ok::<_, Error>(Some(Json(restored)))
+            })
+            .await?
+        }
+        None => None,
+    };
+    let options = match core_options().to_cors() {
+        Ok(a) => a,
+        Err(a) => return Ok(Err(a)),
+    };
+    Ok(options.respond_owned(move |guard| guard.responder(out)))
+}
+
+/// Every row of `place_location`, `place_names`, `place_aliases`, `routes`,
+/// `busses` and `current_location` as JSON, for `POST /admin/backups/import`
+/// elsewhere.
+#[get("/export")]
+async fn export_get<'r, 'o: 'r>(_admin: Admin, db: Db) -> Result<impl Responder<'r, 'o>> {
+    let out = Json(db.run(|conn| export(conn).map_err(Error::from)).await?);
+    let options = match core_options().to_cors() {
+        Ok(a) => a,
+        Err(a) => return Ok(Err(a)),
+    };
+    Ok(options.respond_owned(move |guard| guard.responder(out)))
+}
+
+/// Replaces the dumped tables with an export, in one transaction, and answers
+/// with the rows written by table. Other writes wait until it is done. Dumps
+/// larger than the `json` limit in `Rocket.toml` are refused; use
+/// `bus-server import` for those.
+#[post("/import", data = "<post>")]
+async fn import_post<'r, 'o: 'r>(
+    _admin: Admin,
+    db: Db,
+    actor: Actor,
+    live: &State<Positions>,
+    post: Json<Dump>,
+) -> Result<impl Responder<'r, 'o>> {
+    let dump = post.into_inner();
+    let live = live.inner().clone();
+    let _turn = db.exclusive().await;
+    let imported = db
+        .run(move |conn| {
+            let imported = import(conn, &dump, &actor)?;
+            bus::reload(conn, &live)?;
+            Ok::<_, Error>(imported)
+        })
+        .await?;
+    let out = Json(imported);
+    let options = match core_options().to_cors() {
+        Ok(a) => a,
+        Err(a) => return Ok(Err(a)),
+    };
+    Ok(options.respond_owned(move |guard| guard.responder(out)))
+}
+
+async fn run_migrations(rocket: Rocket<Build>) -> Rocket<Build> {
+    use diesel_migrations::MigrationHarness;
+
+    Db::get_one(&rocket)
+        .await
+        .expect("database connection")
+        .run(|conn| {
+            conn.run_pending_migrations(db::MIGRATIONS)
+                .expect("diesel migrations");
+        })
+        .await;
+
+    rocket
+}
+
+pub fn backup_data() -> AdHoc {
+    AdHoc::try_on_ignite("Backups", |rocket| async {
+        let backups = match Backups::new(rocket.figment()) {
+            Ok(backups) => backups,
+            Err(error) => {
+                error!("[backup]: {}", error);
+                return Err(rocket);
+            }
+        };
+        Ok(rocket
+            .manage(backups)
+            .attach(AdHoc::on_ignite("Diesel Migrations", run_migrations))
+            .mount(
+                "/admin/backups",
+                routes![
+                    list_snapshots,
+                    snapshot_post,
+                    delete_snapshot,
+                    prune_post,
+                    restore_post,
+                    export_get,
+                    import_post
+                ],
+            ))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Backups written to a directory of their own, holding empty files by
+    /// the given names.
+    fn backups(test: &str, files: &[&str]) -> Backups {
+        let dir = std::env::temp_dir().join(format!("bus-server-{test}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        for name in files {
+            fs::write(dir.join(name), b"").unwrap();
+        }
+        Backups {
+            url: String::new(),
+            config: BackupConfig { dir, keep: 10 },
+        }
+    }
+
+    fn names(snapshots: &[Snapshot]) -> Vec<&str> {
+        snapshots
+            .iter()
+            .map(|snapshot| snapshot.name.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn taken_at_only_reads_snapshot_names() {
+        assert_eq!(taken_at("db-1700000000000.sqlite"), Some(1_700_000_000_000));
+        assert_eq!(taken_at("db-.sqlite"), None);
+        assert_eq!(taken_at("db-12a.sqlite"), None);
+        assert_eq!(taken_at("db--1.sqlite"), None);
+        assert_eq!(taken_at("db-1.sqlite.bak"), None);
+        assert_eq!(taken_at("../db-1.sqlite"), None);
+        assert_eq!(taken_at("db-1/../../etc.sqlite"), None);
+    }
+
+    #[test]
+    fn list_is_newest_first_by_time_not_name() {
+        let backups = backups(
+            "list",
+            &[
+                "db-3000.sqlite",
+                "db-20000.sqlite",
+                "db-1000.sqlite",
+                "notes.txt",
+            ],
+        );
+        let listed = backups.list().unwrap();
+        assert_eq!(
+            names(&listed),
+            ["db-20000.sqlite", "db-3000.sqlite", "db-1000.sqlite"]
+        );
+        assert_eq!(listed[0].created_at, 20);
+        fs::remove_dir_all(&backups.config.dir).unwrap();
+    }
+
+    #[test]
+    fn prune_keeps_the_newest() {
+        let backups = backups(
+            "prune",
+            &[
+                "db-3000.sqlite",
+                "db-20000.sqlite",
+                "db-1000.sqlite",
+                "notes.txt",
+            ],
+        );
+        let pruned = backups.prune(1).unwrap();
+        assert_eq!(names(&pruned), ["db-3000.sqlite", "db-1000.sqlite"]);
+        assert_eq!(names(&backups.list().unwrap()), ["db-20000.sqlite"]);
+        assert!(backups.config.dir.join("notes.txt").is_file());
+        assert!(backups.prune(1).unwrap().is_empty());
+        fs::remove_dir_all(&backups.config.dir).unwrap();
+    }
+
+    #[test]
+    fn remove_ignores_other_files() {
+        let backups = backups("remove", &["db-1000.sqlite", "notes.txt"]);
+        assert!(backups.remove("notes.txt").unwrap().is_none());
+        assert!(backups.remove("db-2000.sqlite").unwrap().is_none());
+        assert_eq!(
+            backups.remove("db-1000.sqlite").unwrap().unwrap().name,
+            "db-1000.sqlite"
+        );
+        assert!(backups.list().unwrap().is_empty());
+        fs::remove_dir_all(&backups.config.dir).unwrap();
+    }
+}
@@ -1,25 +1,50 @@
-use rocket::fairing::AdHoc;
-use rocket::response::{Debug, Responder};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashSet};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use rocket::fairing::{self, AdHoc};
+use rocket::http::Status;
+use rocket::response::{
+    status::{BadRequest, Custom},
+    Debug, Responder,
+};
 use rocket::serde::{json::Json, Deserialize, Serialize};
-use rocket::{http, Build, Rocket};
+use rocket::{http, Build, Orbit, Rocket, State};
 
 use rocket_cors::{AllowedHeaders, AllowedOrigins, CorsOptions, Method};
 use rocket_sync_db_pools::diesel;
 
 use self::diesel::prelude::*;
 
-#[database("diesel")]
-struct Db(diesel::SqliteConnection);
+use crate::audit::{Actor, Change};
+use crate::config;
+use crate::db::{self, Db};
+use crate::geo;
+use crate::live::{Durability, LiveConfig, LiveStore};
+use crate::paging::{Bbox, Paging, Sort};
+use crate::routes::Direction;
 
 type Result<T, E = Debug<diesel::result::Error>> = std::result::Result<T, E>;
 
-#[derive(Debug, Clone, Deserialize, Serialize, Queryable, Insertable)]
+#[derive(Debug, Clone, Deserialize, Serialize, Queryable, Insertable, AsChangeset)]
 #[serde(crate = "rocket::serde")]
-#[diesel(table_name = current_location)]
-struct CurrentLocation {
+#[diesel(table_name = current_location, treat_none_as_null = true)]
+pub(crate) struct CurrentLocation {
     busid: String,
     latitude: f32,
     longitude: f32,
+    recorded_at: i64,
+    speed: Option<f32>,
+    heading: Option<f32>,
+    accuracy: Option<f32>,
+    altitude: Option<f32>,
+    odometer: Option<f64>,
+    snapped_latitude: Option<f32>,
+    snapped_longitude: Option<f32>,
+    distance_along: Option<f32>,
+    direction: Option<String>,
+    /// Meters the Kalman filter reckons a smoothed position may be off by.
+    estimated_accuracy: Option<f32>,
 }
 
 table! {
@@ -27,7 +52,546 @@ table! {
         busid -> Text,
         latitude -> Float,
         longitude -> Float,
+        recorded_at -> BigInt,
+        speed -> Nullable<Float>,
+        heading -> Nullable<Float>,
+        accuracy -> Nullable<Float>,
+        altitude -> Nullable<Float>,
+        odometer -> Nullable<Double>,
+        snapped_latitude -> Nullable<Float>,
+        snapped_longitude -> Nullable<Float>,
+        distance_along -> Nullable<Float>,
+        direction -> Nullable<Text>,
+        estimated_accuracy -> Nullable<Float>,
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, Insertable)]
+#[serde(crate = "rocket::serde")]
+#[diesel(table_name = location_history)]
+struct LocationHistory {
+    busid: String,
+    latitude: f32,
+    longitude: f32,
+    recorded_at: i64,
+    speed: Option<f32>,
+    heading: Option<f32>,
+    accuracy: Option<f32>,
+    altitude: Option<f32>,
+    odometer: Option<f64>,
+    snapped_latitude: Option<f32>,
+    snapped_longitude: Option<f32>,
+    distance_along: Option<f32>,
+    direction: Option<String>,
+    routeid: Option<String>,
+}
+
+impl From<&CurrentLocation> for LocationHistory {
+    fn from(location: &CurrentLocation) -> Self {
+        LocationHistory {
+            busid: location.busid.clone(),
+            latitude: location.latitude,
+            longitude: location.longitude,
+            recorded_at: location.recorded_at,
+            speed: location.speed,
+            heading: location.heading,
+            accuracy: location.accuracy,
+            altitude: location.altitude,
+            odometer: location.odometer,
+            snapped_latitude: location.snapped_latitude,
+            snapped_longitude: location.snapped_longitude,
+            distance_along: location.distance_along,
+            direction: location.direction.clone(),
+            routeid: None,
+        }
+    }
+}
+
+table! {
+    location_history (id) {
+        id -> Integer,
+        busid -> Text,
+        latitude -> Float,
+        longitude -> Float,
+        recorded_at -> BigInt,
+        speed -> Nullable<Float>,
+        heading -> Nullable<Float>,
+        accuracy -> Nullable<Float>,
+        altitude -> Nullable<Float>,
+        odometer -> Nullable<Double>,
+        snapped_latitude -> Nullable<Float>,
+        snapped_longitude -> Nullable<Float>,
+        distance_along -> Nullable<Float>,
+        direction -> Nullable<Text>,
+        routeid -> Nullable<Text>,
+    }
+}
+
+table! {
+    route_shapes (routeid) {
+        routeid -> Text,
+        shape -> Text,
+    }
+}
+
+table! {
+    vehicle_assignments (id) {
+        id -> Integer,
+        busid -> Text,
+        routeid -> Text,
+        valid_from -> BigInt,
+        valid_to -> Nullable<BigInt>,
+    }
+}
+
+allow_tables_to_appear_in_same_query!(current_location, vehicle_assignments);
+
+/// A fix dropped by the ingest filter, kept for diagnosing noisy trackers.
+#[derive(Debug, Clone, Deserialize, Serialize, Queryable, Insertable)]
+#[serde(crate = "rocket::serde")]
+#[diesel(table_name = rejected_locations)]
+struct RejectedLocation {
+    busid: String,
+    latitude: f32,
+    longitude: f32,
+    recorded_at: i64,
+    accuracy: Option<f32>,
+    reason: String,
+    received_at: i64,
+}
+
+impl RejectedLocation {
+    fn new(location: &CurrentLocation, reason: &str) -> Self {
+        RejectedLocation {
+            busid: location.busid.clone(),
+            latitude: location.latitude,
+            longitude: location.longitude,
+            recorded_at: location.recorded_at,
+            accuracy: location.accuracy,
+            reason: reason.to_owned(),
+            received_at: now(),
+        }
+    }
+}
+
+table! {
+    rejected_locations (id) {
+        id -> Integer,
+        busid -> Text,
+        latitude -> Float,
+        longitude -> Float,
+        recorded_at -> BigInt,
+        accuracy -> Nullable<Float>,
+        reason -> Text,
+        received_at -> BigInt,
+    }
+}
+
+/// A GPS fix as sent by a tracker. `timestamp` is the device time of the fix
+/// in unix seconds; speed is in m/s, heading in degrees clockwise from north,
+/// and accuracy, altitude and odometer are in meters. Trackers that know
+/// which way the bus runs may send `direction`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+struct Fix {
+    busid: String,
+    latitude: f32,
+    longitude: f32,
+    timestamp: Option<i64>,
+    speed: Option<f32>,
+    heading: Option<f32>,
+    accuracy: Option<f32>,
+    altitude: Option<f32>,
+    odometer: Option<f64>,
+    direction: Option<Direction>,
+}
+
+impl Fix {
+    fn into_location(self, recorded_at: i64) -> CurrentLocation {
+        CurrentLocation {
+            busid: self.busid,
+            latitude: self.latitude,
+            longitude: self.longitude,
+            recorded_at,
+            speed: self.speed,
+            heading: self.heading,
+            accuracy: self.accuracy,
+            altitude: self.altitude,
+            odometer: self.odometer,
+            snapped_latitude: None,
+            snapped_longitude: None,
+            distance_along: None,
+            direction: self
+                .direction
+                .map(|direction| direction.as_str().to_owned()),
+            estimated_accuracy: None,
+        }
+    }
+}
+
+/// A partial correction of a bus position; unset fields keep their value.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+struct LocationPatch {
+    latitude: Option<f32>,
+    longitude: Option<f32>,
+    timestamp: Option<i64>,
+    speed: Option<f32>,
+    heading: Option<f32>,
+    accuracy: Option<f32>,
+    altitude: Option<f32>,
+    odometer: Option<f64>,
+    direction: Option<Direction>,
+}
+
+impl LocationPatch {
+    fn apply(self, location: &mut CurrentLocation) {
+        location.latitude = self.latitude.unwrap_or(location.latitude);
+        location.longitude = self.longitude.unwrap_or(location.longitude);
+        location.recorded_at = self.timestamp.unwrap_or(location.recorded_at);
+        location.speed = self.speed.or(location.speed);
+        location.heading = self.heading.or(location.heading);
+        location.accuracy = self.accuracy.or(location.accuracy);
+        location.altitude = self.altitude.or(location.altitude);
+        location.odometer = self.odometer.or(location.odometer);
+        if let Some(direction) = self.direction {
+            location.direction = Some(direction.as_str().to_owned());
+        }
+    }
+}
+
+/// How recently a bus last reported, for `?status=` on `/bus/all`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromFormField)]
+enum BusStatus {
+    /// Reported within the last `LIVE_WINDOW` seconds.
+    Live,
+    /// Reported before, but not recently.
+    Stale,
+    /// Never reported a fix.
+    Unreported,
+}
+
+const LIVE_WINDOW: i64 = 300;
+
+/// Fields `/bus/all` can be sorted by.
+const SORTS: &[&str] = &["busid", "recorded_at", "latitude", "longitude", "speed"];
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(crate = "rocket::serde")]
+struct RejectedFix {
+    busid: String,
+    timestamp: Option<i64>,
+    reason: &'static str,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(crate = "rocket::serde")]
+struct BatchOutcome {
+    accepted: usize,
+    rejected: Vec<RejectedFix>,
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+/// Fills in speed and heading from the previous fix when the device did not
+/// send them. A `recorded_at` of 0 means the bus has no real fix yet.
+fn derive_motion(prev: &CurrentLocation, next: &mut CurrentLocation) {
+    let elapsed = next.recorded_at - prev.recorded_at;
+    if prev.recorded_at == 0 || elapsed <= 0 {
+        return;
+    }
+    let from = (prev.latitude, prev.longitude);
+    let to = (next.latitude, next.longitude);
+    let distance = geo::distance(from, to);
+    if next.speed.is_none() {
+        next.speed = Some(distance / elapsed as f32);
+    }
+    if next.heading.is_none() && distance > 0.0 {
+        next.heading = Some(geo::bearing(from, to));
+    }
+}
+
+/// Ingest filter settings, read from `gps_filter` in `Rocket.toml`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+struct GpsFilter {
+    /// Fixes implying a speed above this many m/s since the last fix are jumps.
+    max_speed: f32,
+    /// Fixes reporting an accuracy worse than this many meters are dropped.
+    max_accuracy: f32,
+    /// Smooth accepted fixes with a Kalman filter.
+    kalman: bool,
+    /// Kalman process noise in m/s, i.e. how fast the true position may drift
+    /// away from the last estimate.
+    process_noise: f32,
+}
+
+impl Default for GpsFilter {
+    fn default() -> Self {
+        GpsFilter {
+            max_speed: 42.0,
+            max_accuracy: 100.0,
+            kalman: false,
+            process_noise: 3.0,
+        }
+    }
+}
+
+impl GpsFilter {
+    /// Checks `next` against the last accepted fix, smoothing it in place when
+    /// enabled. Returns the reason when the fix should be rejected; a fix no
+    /// newer than the last one is stale.
+    fn apply(
+        &self,
+        prev: &CurrentLocation,
+        next: &mut CurrentLocation,
+    ) -> Result<(), &'static str> {
+        if next
+            .accuracy
+            .is_some_and(|accuracy| accuracy > self.max_accuracy)
+        {
+            return Err("low accuracy");
+        }
+        if prev.recorded_at == 0 {
+            return Ok(());
+        }
+        let elapsed = next.recorded_at - prev.recorded_at;
+        if elapsed <= 0 {
+            return Err("stale");
+        }
+        let distance = geo::distance(
+            (prev.latitude, prev.longitude),
+            (next.latitude, next.longitude),
+        );
+        if distance / elapsed as f32 > self.max_speed {
+            return Err("implausible jump");
+        }
+        if self.kalman {
+            self.smooth(prev, next, elapsed as f32);
+        }
+        Ok(())
+    }
+
+    /// One step of a constant-position Kalman filter. The previous fix's
+    /// `estimated_accuracy`, or its reported accuracy before any smoothing,
+    /// is the estimate's standard deviation. The reported accuracy is kept as
+    /// the tracker sent it.
+    fn smooth(&self, prev: &CurrentLocation, next: &mut CurrentLocation, elapsed: f32) {
+        let measured = next.accuracy.unwrap_or(self.max_accuracy).max(1.0);
+        let estimated = prev
+            .estimated_accuracy
+            .or(prev.accuracy)
+            .unwrap_or(measured);
+        let variance = estimated.powi(2) + elapsed * self.process_noise.powi(2);
+        let gain = variance / (variance + measured.powi(2));
+        next.latitude = prev.latitude + gain * (next.latitude - prev.latitude);
+        next.longitude = prev.longitude + gain * (next.longitude - prev.longitude);
+        next.estimated_accuracy = Some(((1.0 - gain) * variance).sqrt());
+    }
+}
+
+/// The route `busid` is currently assigned to, if any.
+fn active_route(conn: &mut db::Connection, busid: &str) -> QueryResult<Option<String>> {
+    vehicle_assignments::table
+        .select(vehicle_assignments::routeid)
+        .filter(vehicle_assignments::busid.eq(busid))
+        .filter(vehicle_assignments::valid_to.is_null())
+        .first(conn)
+        .optional()
+}
+
+/// Loads the road geometry of `routeid`, if one was uploaded.
+fn load_shape(conn: &mut db::Connection, routeid: Option<&str>) -> QueryResult<Vec<(f32, f32)>> {
+    let routeid = match routeid {
+        Some(routeid) => routeid,
+        None => return Ok(vec![]),
+    };
+    let shape: Option<String> = route_shapes::table
+        .select(route_shapes::shape)
+        .filter(route_shapes::routeid.eq(routeid))
+        .first(conn)
+        .optional()?;
+    Ok(shape.as_deref().map(geo::decode_path).unwrap_or_default())
+}
+
+/// Projects the raw position onto the route shape, keeping both.
+fn snap(shape: &[(f32, f32)], location: &mut CurrentLocation) {
+    if let Some(projection) = geo::project(shape, (location.latitude, location.longitude)) {
+        location.snapped_latitude = Some(projection.point.0);
+        location.snapped_longitude = Some(projection.point.1);
+        location.distance_along = Some(projection.along);
+    }
+}
+
+/// Works out which way along its route the bus runs, unless the tracker said
+/// so: moving forward along the route shape is outbound, moving back is
+/// inbound, and otherwise the previous direction is kept.
+fn derive_direction(prev: &CurrentLocation, next: &mut CurrentLocation) {
+    if next.direction.is_some() {
+        return;
+    }
+    let direction = match (prev.distance_along, next.distance_along) {
+        (Some(from), Some(to)) if to - from > 10.0 => Some(Direction::Outbound),
+        (Some(from), Some(to)) if from - to > 10.0 => Some(Direction::Inbound),
+        _ => None,
+    };
+    next.direction = match direction {
+        Some(direction) => Some(direction.as_str().to_owned()),
+        None => prev.direction.clone(),
+    };
+}
+
+/// Positions of every bus, kept in memory and written to `current_location`
+/// as configured in `[live_positions]`.
+pub(crate) type Positions = LiveStore<CurrentLocation>;
+
+/// The current position of `busid`, from memory or else the database.
+fn current(
+    conn: &mut db::Connection,
+    live: &Positions,
+    busid: &str,
+) -> QueryResult<Option<CurrentLocation>> {
+    if let Some(location) = live.get(busid) {
+        return Ok(Some(location));
+    }
+    let location: Option<CurrentLocation> = current_location::table
+        .filter(current_location::busid.eq(busid))
+        .first(conn)
+        .optional()?;
+    if let Some(location) = &location {
+        live.load(location.busid.clone(), location.clone());
+    }
+    Ok(location)
+}
+
+/// Writes a position with one keyed `UPDATE`, which is all a known bus
+/// needs, and only falls back to an upsert when no row was there to update.
+/// `benches/ingest.rs` measures it as `POST /bus` runs it with positions
+/// written immediately.
+fn upsert(conn: &mut db::Connection, location: &CurrentLocation) -> QueryResult<usize> {
+    let updated = diesel::update(current_location::table.find(&location.busid))
+        .set(location)
+        .execute(conn)?;
+    if updated > 0 {
+        return Ok(updated);
     }
+    diesel::insert_into(current_location::table)
+        .values(location)
+        .on_conflict(current_location::busid)
+        .do_update()
+        .set(location)
+        .execute(conn)
+}
+
+/// Keeps a position that is already in the database, or will be by the next
+/// flush, in memory.
+fn remember(live: &Positions, location: CurrentLocation) {
+    match live.config.durability {
+        Durability::Immediate => live.load(location.busid.clone(), location),
+        Durability::Batched => live.put(location.busid.clone(), location),
+    }
+}
+
+/// Records a new position: in memory, and in the database too unless
+/// positions are batched.
+fn store(
+    conn: &mut db::Connection,
+    live: &Positions,
+    location: CurrentLocation,
+) -> QueryResult<()> {
+    if live.config.durability == Durability::Immediate {
+        upsert(conn, &location)?;
+    }
+    remember(live, location);
+    Ok(())
+}
+
+/// Writes the positions changed since the last flush in one transaction.
+fn flush(conn: &mut db::Connection, live: &Positions) -> QueryResult<usize> {
+    let rows = live.take_dirty();
+    if rows.is_empty() {
+        return Ok(0);
+    }
+    let written = conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        for row in &rows {
+            upsert(conn, row)?;
+        }
+        Ok(rows.len())
+    });
+    if written.is_err() {
+        live.restore(rows.into_iter().map(|row| row.busid));
+    }
+    written
+}
+
+/// Picks up the position of `busid` if another module just gave the bus its
+/// first one in the database. A position already in memory is kept.
+pub(crate) fn track(conn: &mut db::Connection, live: &Positions, busid: &str) -> QueryResult<()> {
+    current(conn, live, busid).map(|_| ())
+}
+
+/// The direction `busid` was last seen running its route in, as stored in
+/// `direction`; `None` when not known.
+pub(crate) fn direction(
+    conn: &mut db::Connection,
+    live: &Positions,
+    busid: &str,
+) -> QueryResult<Option<String>> {
+    Ok(current(conn, live, busid)?.and_then(|location| location.direction))
+}
+
+/// Moves `busid` to a position set by hand rather than reported, as
+/// `POST /routes` does, keeping the rest of what is known of the bus. It is
+/// stored like a fix, so an older unflushed position can't overwrite it.
+pub(crate) fn relocate(
+    conn: &mut db::Connection,
+    live: &Positions,
+    busid: &str,
+    latitude: f32,
+    longitude: f32,
+) -> QueryResult<()> {
+    let location = match current(conn, live, busid)? {
+        Some(location) => CurrentLocation {
+            latitude,
+            longitude,
+            ..location
+        },
+        None => CurrentLocation {
+            busid: busid.to_owned(),
+            latitude,
+            longitude,
+            recorded_at: 0,
+            speed: None,
+            heading: None,
+            accuracy: None,
+            altitude: None,
+            odometer: None,
+            snapped_latitude: None,
+            snapped_longitude: None,
+            distance_along: None,
+            direction: None,
+            estimated_accuracy: None,
+        },
+    };
+    store(conn, live, location)
+}
+
+/// Replaces every position in memory with what `current_location` holds,
+/// after the table was replaced wholesale. Unflushed positions are dropped.
+pub(crate) fn reload(conn: &mut db::Connection, live: &Positions) -> QueryResult<()> {
+    for busid in live.ids() {
+        live.forget(&busid);
+    }
+    let rows: Vec<CurrentLocation> = current_location::table.load(conn)?;
+    for row in rows {
+        live.load(row.busid.clone(), row);
+    }
+    Ok(())
 }
 
 fn core_options() -> CorsOptions {
@@ -38,6 +602,8 @@ fn core_options() -> CorsOptions {
             http::Method::Post,
             http::Method::Options,
             http::Method::Delete,
+            http::Method::Put,
+            http::Method::Patch,
         ]
         .into_iter()
         .map(Method)
@@ -53,30 +619,41 @@ fn core_options() -> CorsOptions {
 #[post("/", data = "<post>")]
 async fn bus_post<'r, 'o: 'r>(
     db: Db,
-    post: Json<CurrentLocation>,
+    filter: &State<GpsFilter>,
+    live: &State<Positions>,
+    post: Json<Fix>,
 ) -> Result<impl Responder<'r, 'o>> {
-    let post_value = post.clone();
+    let filter = filter.inner().clone();
+    let live = live.inner().clone();
+    let post_value = post.into_inner();
+    let recorded_at = post_value.timestamp.unwrap_or_else(now);
+    let mut post_value = post_value.into_location(recorded_at);
     let a: bool = db
-        .run(move |conn| {
-            let b = &*post_value.clone().busid;
-            let a = match current_location::table
-                .select(current_location::busid)
-                .load::<String>(conn)
-            {
-                Ok(a) => a.contains(&b.to_string()),
-                Err(_) => false,
-            };
-            if a {
-                match diesel::replace_into(current_location::table)
-                    .values(&*post_value)
-                    .execute(conn)
-                {
-                    Ok(_) => Ok(true),
-                    Err(a) => Err(a),
+        .write(move |conn| {
+            conn.transaction::<_, diesel::result::Error, _>(|conn| {
+                let prev = match current(conn, &live, &post_value.busid)? {
+                    Some(prev) => prev,
+                    None => return Ok(false),
+                };
+                if let Err(reason) = filter.apply(&prev, &mut post_value) {
+                    diesel::insert_into(rejected_locations::table)
+                        .values(RejectedLocation::new(&post_value, reason))
+                        .execute(conn)?;
+                    return Ok(false);
                 }
-            } else {
-                Ok(false)
-            }
+                derive_motion(&prev, &mut post_value);
+                let routeid = active_route(conn, &post_value.busid)?;
+                snap(&load_shape(conn, routeid.as_deref())?, &mut post_value);
+                derive_direction(&prev, &mut post_value);
+                diesel::insert_into(location_history::table)
+                    .values(LocationHistory {
+                        routeid,
+                        ..LocationHistory::from(&post_value)
+                    })
+                    .execute(conn)?;
+                store(conn, &live, post_value)?;
+                Ok(true)
+            })
         })
         .await?;
     let options = match core_options().to_cors() {
@@ -86,30 +663,119 @@ async fn bus_post<'r, 'o: 'r>(
     Ok(options.respond_owned(move |guard| guard.responder(Json(a))))
 }
 
-#[get("/")]
-async fn list<'r, 'o: 'r>(db: Db) -> Result<impl Responder<'r, 'o>> {
-    let ids: Vec<String> = db
-        .run(move |conn| {
-            current_location::table
-                .select(current_location::busid)
-                .load(conn)
+/// Ingests fixes buffered by trackers while they were offline. Fixes may
+/// belong to several buses and arrive in any order; they are stored in
+/// `location_history` in one transaction, and `current_location` only moves to
+/// the newest accepted fix of each bus. Fixes for unknown buses, without a
+/// timestamp, not newer than the bus's current location, or dropped by the
+/// ingest filter are rejected and reported back.
+#[post("/batch", data = "<post>")]
+async fn bus_batch<'r, 'o: 'r>(
+    db: Db,
+    filter: &State<GpsFilter>,
+    live: &State<Positions>,
+    post: Json<Vec<Fix>>,
+) -> Result<impl Responder<'r, 'o>> {
+    let filter = filter.inner().clone();
+    let live = live.inner().clone();
+    let fixes = post.into_inner();
+    let out = db
+        .write(move |conn| {
+            let mut newest = vec![];
+            let outcome = conn.transaction::<_, diesel::result::Error, _>(|conn| {
+                let mut outcome = BatchOutcome::default();
+                let mut by_bus: BTreeMap<String, Vec<(i64, Fix)>> = BTreeMap::new();
+                for fix in fixes {
+                    match fix.timestamp {
+                        Some(timestamp) => by_bus
+                            .entry(fix.busid.clone())
+                            .or_default()
+                            .push((timestamp, fix)),
+                        None => outcome.rejected.push(RejectedFix {
+                            busid: fix.busid,
+                            timestamp: None,
+                            reason: "missing timestamp",
+                        }),
+                    }
+                }
+                for (busid, mut fixes) in by_bus {
+                    fixes.sort_by_key(|(timestamp, _)| *timestamp);
+                    let mut prev = match current(conn, &live, &busid)? {
+                        Some(current) => current,
+                        None => {
+                            outcome
+                                .rejected
+                                .extend(fixes.into_iter().map(|(timestamp, fix)| RejectedFix {
+                                    busid: fix.busid,
+                                    timestamp: Some(timestamp),
+                                    reason: "unknown bus",
+                                }));
+                            continue;
+                        }
+                    };
+                    let routeid = active_route(conn, &busid)?;
+                    let shape = load_shape(conn, routeid.as_deref())?;
+                    let mut moved = false;
+                    for (timestamp, fix) in fixes {
+                        if timestamp <= prev.recorded_at {
+                            outcome.rejected.push(RejectedFix {
+                                busid: fix.busid,
+                                timestamp: Some(timestamp),
+                                reason: "not newer than current location",
+                            });
+                            continue;
+                        }
+                        let mut location = fix.into_location(timestamp);
+                        if let Err(reason) = filter.apply(&prev, &mut location) {
+                            diesel::insert_into(rejected_locations::table)
+                                .values(RejectedLocation::new(&location, reason))
+                                .execute(conn)?;
+                            outcome.rejected.push(RejectedFix {
+                                busid: location.busid,
+                                timestamp: Some(timestamp),
+                                reason,
+                            });
+                            continue;
+                        }
+                        derive_motion(&prev, &mut location);
+                        snap(&shape, &mut location);
+                        derive_direction(&prev, &mut location);
+                        diesel::insert_into(location_history::table)
+                            .values(LocationHistory {
+                                routeid: routeid.clone(),
+                                ..LocationHistory::from(&location)
+                            })
+                            .execute(conn)?;
+                        outcome.accepted += 1;
+                        prev = location;
+                        moved = true;
+                    }
+                    if moved {
+                        if live.config.durability == Durability::Immediate {
+                            upsert(conn, &prev)?;
+                        }
+                        newest.push(prev);
+                    }
+                }
+                Ok(outcome)
+            })?;
+            for location in newest {
+                remember(&live, location);
+            }
+            Ok::<_, diesel::result::Error>(outcome)
         })
         .await?;
     let options = match core_options().to_cors() {
         Ok(a) => a,
         Err(a) => return Ok(Err(a)),
     };
-    Ok(options.respond_owned(move |guard| guard.responder(Json(ids))))
+    Ok(options.respond_owned(move |guard| guard.responder(Json(out))))
 }
 
-#[get("/all")]
-async fn list_all<'r, 'o: 'r>(db: Db) -> Result<impl Responder<'r, 'o>> {
-    let ids = db
-        .run(move |conn| {
-            current_location::table
-                .load::<CurrentLocation>(conn)
-        })
-        .await?;
+#[get("/")]
+async fn list<'r, 'o: 'r>(live: &State<Positions>) -> Result<impl Responder<'r, 'o>> {
+    let mut ids = live.ids();
+    ids.sort();
     let options = match core_options().to_cors() {
         Ok(a) => a,
         Err(a) => return Ok(Err(a)),
@@ -117,17 +783,102 @@ async fn list_all<'r, 'o: 'r>(db: Db) -> Result<impl Responder<'r, 'o>> {
     Ok(options.respond_owned(move |guard| guard.responder(Json(ids))))
 }
 
+/// Bus positions, optionally filtered by `?route=`, `?bbox=` and `?status=`,
+/// sorted by `?sort=` and paged with `?limit=` and `?offset=`.
+#[get("/all?<route>&<bbox>&<status>&<sort>&<paging..>")]
+async fn list_all<'r, 'o: 'r>(
+    db: Db,
+    live: &State<Positions>,
+    route: Option<String>,
+    bbox: Option<Bbox>,
+    status: Option<BusStatus>,
+    sort: Option<Sort>,
+    paging: Paging,
+) -> Result<impl Responder<'r, 'o>> {
+    let sort = sort.unwrap_or(Sort {
+        field: "busid".to_owned(),
+        descending: false,
+    });
+    let out = if SORTS.contains(&&*sort.field) {
+        let assigned: Option<HashSet<String>> = match route {
+            Some(route) => Some(
+                db.run(move |conn| {
+                    vehicle_assignments::table
+                        .select(vehicle_assignments::busid)
+                        .filter(vehicle_assignments::routeid.eq(route))
+                        .filter(vehicle_assignments::valid_to.is_null())
+                        .load::<String>(conn)
+                })
+                .await?
+                .into_iter()
+                .collect(),
+            ),
+            None => None,
+        };
+        let cutoff = now() - LIVE_WINDOW;
+        let mut rows: Vec<CurrentLocation> = live
+            .all()
+            .into_iter()
+            .filter(|location| match &assigned {
+                Some(assigned) => assigned.contains(&location.busid),
+                None => true,
+            })
+            .filter(|location| match &bbox {
+                Some(bbox) => {
+                    (bbox.min_lat..=bbox.max_lat).contains(&location.latitude)
+                        && (bbox.min_lon..=bbox.max_lon).contains(&location.longitude)
+                }
+                None => true,
+            })
+            .filter(|location| match status {
+                Some(BusStatus::Live) => location.recorded_at >= cutoff,
+                Some(BusStatus::Stale) => (1..cutoff).contains(&location.recorded_at),
+                Some(BusStatus::Unreported) => location.recorded_at == 0,
+                None => true,
+            })
+            .collect();
+        rows.sort_by(|a, b| {
+            let order = match sort.field.as_str() {
+                "recorded_at" => a.recorded_at.cmp(&b.recorded_at),
+                "latitude" => a.latitude.total_cmp(&b.latitude),
+                "longitude" => a.longitude.total_cmp(&b.longitude),
+                "speed" => a.speed.partial_cmp(&b.speed).unwrap_or(Ordering::Equal),
+                _ => a.busid.cmp(&b.busid),
+            };
+            let order = if sort.descending {
+                order.reverse()
+            } else {
+                order
+            };
+            order.then_with(|| a.busid.cmp(&b.busid))
+        });
+        let rows = rows
+            .into_iter()
+            .skip(usize::try_from(paging.offset()).unwrap_or(usize::MAX))
+            .take(usize::try_from(paging.fetch()).unwrap_or(usize::MAX))
+            .collect();
+        Ok(paging.page(rows))
+    } else {
+        Err(BadRequest(Some("unknown sort field")))
+    };
+    let options = match core_options().to_cors() {
+        Ok(a) => a,
+        Err(a) => return Ok(Err(a)),
+    };
+    Ok(options.respond_owned(move |guard| guard.responder(out)))
+}
 
 #[get("/one/<id>")]
-async fn get_one_bus<'r, 'o: 'r>(db: Db, id: String) -> Result<impl Responder<'r, 'o>> {
-    let out: Json<CurrentLocation> = db
-        .run(move |conn| {
-            current_location::table
-                .filter(current_location::busid.eq(id))
-                .first(conn)
-        })
-        .await
-        .map(Json)?;
+async fn get_one_bus<'r, 'o: 'r>(
+    db: Db,
+    live: &State<Positions>,
+    id: String,
+) -> Result<impl Responder<'r, 'o>> {
+    let live = live.inner().clone();
+    let out: Option<Json<CurrentLocation>> = db
+        .run(move |conn| current(conn, &live, &id))
+        .await?
+        .map(Json);
 
     let options = match core_options().to_cors() {
         Ok(a) => a,
@@ -136,17 +887,147 @@ async fn get_one_bus<'r, 'o: 'r>(db: Db, id: String) -> Result<impl Responder<'r
     Ok(options.respond_owned(move |guard| guard.responder(out)))
 }
 
-#[delete("/one/<id>")]
-async fn delete_one_bus<'r, 'o: 'r>(db: Db, id: String) -> Result<impl Responder<'r, 'o>> {
-    let out: usize = db
+/// Sets the position of a bus outright, bypassing the ingest filter, and
+/// answers 201 when the bus is new or 200 otherwise.
+#[put("/one/<id>", data = "<post>")]
+async fn put_one_bus<'r, 'o: 'r>(
+    db: Db,
+    actor: Actor,
+    live: &State<Positions>,
+    id: String,
+    post: Json<Fix>,
+) -> Result<impl Responder<'r, 'o>> {
+    let post_value = post.into_inner();
+    let out = if post_value.busid == id {
+        let recorded_at = post_value.timestamp.unwrap_or_else(now);
+        let mut location = post_value.into_location(recorded_at);
+        let live = live.inner().clone();
+        let stored = db
+            .write(move |conn| {
+                conn.transaction::<_, diesel::result::Error, _>(|conn| {
+                    let previous = current(conn, &live, &id)?;
+                    let routeid = active_route(conn, &id)?;
+                    snap(&load_shape(conn, routeid.as_deref())?, &mut location);
+                    let action = if previous.is_some() {
+                        "update"
+                    } else {
+                        "create"
+                    };
+                    Change::new(action, "bus_position", &id)
+                        .before(&previous)
+                        .after(&location)
+                        .record(conn, &actor)?;
+                    store(conn, &live, location.clone())?;
+                    let status = match previous {
+                        Some(_) => Status::Ok,
+                        None => Status::Created,
+                    };
+                    Ok(Custom(status, Json(location)))
+                })
+            })
+            .await?;
+        Ok(stored)
+    } else {
+        Err(BadRequest(Some("busid does not match the path")))
+    };
+    let options = match core_options().to_cors() {
+        Ok(a) => a,
+        Err(a) => return Ok(Err(a)),
+    };
+    Ok(options.respond_owned(move |guard| guard.responder(out)))
+}
+
+/// Corrects the given fields of a bus position.
+#[patch("/one/<id>", data = "<post>")]
+async fn patch_one_bus<'r, 'o: 'r>(
+    db: Db,
+    actor: Actor,
+    live: &State<Positions>,
+    id: String,
+    post: Json<LocationPatch>,
+) -> Result<impl Responder<'r, 'o>> {
+    let patch = post.into_inner();
+    let live = live.inner().clone();
+    let out = db
+        .write(move |conn| {
+            conn.transaction::<_, diesel::result::Error, _>(|conn| {
+                let previous = match current(conn, &live, &id)? {
+                    Some(location) => location,
+                    None => return Ok(None),
+                };
+                let mut location = previous.clone();
+                patch.apply(&mut location);
+                let routeid = active_route(conn, &id)?;
+                snap(&load_shape(conn, routeid.as_deref())?, &mut location);
+                Change::new("update", "bus_position", &id)
+                    .before(&previous)
+                    .after(&location)
+                    .record(conn, &actor)?;
+                store(conn, &live, location.clone())?;
+                Ok(Some(Json(location)))
+            })
+        })
+        .await?;
+    let options = match core_options().to_cors() {
+        Ok(a) => a,
+        Err(a) => return Ok(Err(a)),
+    };
+    Ok(options.respond_owned(move |guard| guard.responder(out)))
+}
+
+/// Fixes of one bus dropped by the ingest filter, newest first.
+#[get("/rejected/<id>")]
+async fn list_rejected<'r, 'o: 'r>(db: Db, id: String) -> Result<impl Responder<'r, 'o>> {
+    let out: Vec<RejectedLocation> = db
         .run(move |conn| {
-            diesel::delete(current_location::table)
-                .filter(current_location::busid.eq(id))
-                .execute(conn)
+            rejected_locations::table
+                .select((
+                    rejected_locations::busid,
+                    rejected_locations::latitude,
+                    rejected_locations::longitude,
+                    rejected_locations::recorded_at,
+                    rejected_locations::accuracy,
+                    rejected_locations::reason,
+                    rejected_locations::received_at,
+                ))
+                .filter(rejected_locations::busid.eq(id))
+                .order(rejected_locations::id.desc())
+                .limit(100)
+                .load(conn)
+        })
+        .await?;
+    let options = match core_options().to_cors() {
+        Ok(a) => a,
+        Err(a) => return Ok(Err(a)),
+    };
+    Ok(options.respond_owned(move |guard| guard.responder(Json(out))))
+}
+
+#[delete("/one/<id>")]
+async fn delete_one_bus<'r, 'o: 'r>(
+    db: Db,
+    actor: Actor,
+    live: &State<Positions>,
+    id: String,
+) -> Result<impl Responder<'r, 'o>> {
+    let live = live.inner().clone();
+    let out: bool = db
+        .write(move |conn| {
+            let previous = current(conn, &live, &id)?;
+            let deleted = diesel::delete(current_location::table)
+                .filter(current_location::busid.eq(&id))
+                .execute(conn)?;
+            let cached = live.forget(&id);
+            if let Some(previous) = previous {
+                Change::new("delete", "bus_position", &id)
+                    .before(&previous)
+                    .record(conn, &actor)?;
+            }
+            Ok::<_, diesel::result::Error>(deleted == 1 || cached)
         })
         .await?;
 
-    let out = (out == 1).then_some(());
+    let out = out.then_some(());
     let options = match core_options().to_cors() {
         Ok(a) => a,
         Err(a) => return Ok(Err(a)),
@@ -155,15 +1036,13 @@ async fn delete_one_bus<'r, 'o: 'r>(db: Db, id: String) -> Result<impl Responder
 }
 
 async fn run_migrations(rocket: Rocket<Build>) -> Rocket<Build> {
-    use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
-
-    const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
+    use diesel_migrations::MigrationHarness;
 
     Db::get_one(&rocket)
         .await
         .expect("database connection")
         .run(|conn| {
-            conn.run_pending_migrations(MIGRATIONS)
+            conn.run_pending_migrations(db::MIGRATIONS)
                 .expect("diesel migrations");
         })
         .await;
@@ -171,11 +1050,86 @@ async fn run_migrations(rocket: Rocket<Build>) -> Rocket<Build> {
     rocket
 }
 
+/// Loads every stored position into memory.
+async fn warm_positions(rocket: Rocket<Build>) -> fairing::Result {
+    let config: LiveConfig = match config::read(&rocket, "live_positions") {
+        Some(config) => config,
+        None => return Err(rocket),
+    };
+    let rows: Vec<CurrentLocation> = Db::get_one(&rocket)
+        .await
+        .expect("database connection")
+        .run(|conn| current_location::table.load(conn))
+        .await
+        .expect("current positions");
+    let positions = Positions::new(config, rows.into_iter().map(|row| (row.busid.clone(), row)));
+    Ok(rocket.manage(positions))
+}
+
+/// Flushes batched positions every `flush_interval` seconds.
+async fn start_flushing(rocket: &Rocket<Orbit>) {
+    let live = match rocket.state::<Positions>() {
+        Some(live) if live.config.durability == Durability::Batched => live.clone(),
+        _ => return,
+    };
+    let db = Db::get_one(rocket).await.expect("database connection");
+    let period = Duration::from_secs(live.config.flush_interval.max(1));
+    rocket::tokio::spawn(async move {
+        let mut interval = rocket::tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            let live = live.clone();
+            let flushed = db.write(move |conn| flush(conn, &live)).await;
+            if let Err(e) = flushed {
+                error!("flushing bus positions: {}", e);
+            }
+        }
+    });
+}
+
+/// Writes whatever is left unflushed before the server stops.
+async fn final_flush(rocket: &Rocket<Orbit>) {
+    let live = match rocket.state::<Positions>() {
+        Some(live) => live.clone(),
+        None => return,
+    };
+    if let Some(db) = Db::get_one(rocket).await {
+        let flushed = db.write(move |conn| flush(conn, &live)).await;
+        if let Err(e) = flushed {
+            error!("flushing bus positions: {}", e);
+        }
+    }
+}
+
 pub fn bus_data() -> AdHoc {
-    AdHoc::on_ignite("Data related to busses", |rocket| async {
-        rocket
-            .attach(Db::fairing())
+    AdHoc::try_on_ignite("Data related to busses", |rocket| async {
+        let filter: GpsFilter = match config::read(&rocket, "gps_filter") {
+            Some(filter) => filter,
+            None => return Err(rocket),
+        };
+        Ok(rocket
+            .manage(filter)
             .attach(AdHoc::on_ignite("Diesel Migrations", run_migrations))
-            .mount("/bus", routes![bus_post, list, list_all, get_one_bus, delete_one_bus])
+            .attach(AdHoc::try_on_ignite("Live bus positions", warm_positions))
+            .attach(AdHoc::on_liftoff("Flush bus positions", |rocket| {
+                Box::pin(start_flushing(rocket))
+            }))
+            .attach(AdHoc::on_shutdown("Flush bus positions", |rocket| {
+                Box::pin(final_flush(rocket))
+            }))
+            .mount(
+                "/bus",
+                routes![
+                    bus_post,
+                    bus_batch,
+                    list,
+                    list_all,
+                    get_one_bus,
+                    put_one_bus,
+                    patch_one_bus,
+                    list_rejected,
+                    delete_one_bus
+                ],
+            ))
     })
 }
@@ -1,14 +1,20 @@
 use rocket::fairing::AdHoc;
-use rocket::response::{Debug, Responder};
+use rocket::response::{status::BadRequest, Debug, Responder};
 use rocket::serde::{json::Json, Deserialize, Serialize};
-use rocket::{http, Build, Rocket};
+use rocket::{http, Build, Rocket, State};
 
+use self::diesel::dsl::sql;
 use self::diesel::prelude::*;
+use self::diesel::sql_types::{Bool, Text};
 use rocket_cors::{AllowedHeaders, AllowedOrigins, CorsOptions, Method};
 use rocket_sync_db_pools::diesel;
 
-#[database("diesel")]
-struct Db(diesel::SqliteConnection);
+use crate::audit::{Actor, Change};
+use crate::cache::{Conditional, ResponseCache};
+use crate::db::{self, Db};
+use crate::locale::{self, Languages};
+use crate::paging::{Paging, Sort};
+use crate::revisions::LiveEdit;
 
 type Result<T, E = Debug<diesel::result::Error>> = std::result::Result<T, E>;
 
@@ -20,6 +26,15 @@ struct Busses {
     busid: String,
 }
 
+/// The buses at a place, with the place's name in the client's language.
+#[derive(Debug, Clone, Serialize)]
+#[serde(crate = "rocket::serde")]
+struct LocalizedBusses {
+    #[serde(flatten)]
+    busses: Busses,
+    name: String,
+}
+
 table! {
     busses (placeid) {
         placeid -> Text,
@@ -47,13 +62,53 @@ fn core_options() -> CorsOptions {
     }
 }
 
-#[get("/")]
-async fn list<'r, 'o: 'r>(db: Db) -> Result<impl Responder<'r, 'o>> {
-    let ids: Vec<String> = db
-        .run(move |conn| busses::table.select(busses::placeid).load(conn))
-        .await?;
-
-    let out = Json(ids);
+/// Place ids, optionally only those `?bus=` is listed at, sorted by
+/// `?sort=placeid` or `?sort=-placeid` and paged with `?limit=` and `?offset=`.
+#[get("/?<bus>&<sort>&<paging..>")]
+async fn list<'r, 'o: 'r>(
+    db: Db,
+    cache: &State<ResponseCache>,
+    conditional: Conditional,
+    bus: Option<String>,
+    sort: Option<Sort>,
+    paging: Paging,
+) -> Result<impl Responder<'r, 'o>> {
+    let out = match sort {
+        Some(sort) if sort.field != "placeid" => Err(BadRequest(Some("unknown sort field"))),
+        sort => {
+            let descending = sort.is_some_and(|sort| sort.descending);
+            let entry = match cache.get(&conditional) {
+                Some(entry) => entry,
+                None => {
+                    let version = cache.version();
+                    let ids: Vec<String> = db
+                        .run(move |conn| {
+                            let mut query = busses::table.select(busses::placeid).into_boxed();
+                            if let Some(bus) = bus {
+                                query = query.filter(
+                                    sql::<Bool>("instr('|' || busid || '|', '|' || ")
+                                        .bind::<Text, _>(bus)
+                                        .sql(" || '|') > 0"),
+                                );
+                            }
+                            query = if descending {
+                                query.order(busses::placeid.desc())
+                            } else {
+                                query.order(busses::placeid.asc())
+                            };
+                            query
+                                .limit(paging.fetch())
+                                .offset(paging.offset())
+                                .load(conn)
+                        })
+                        .await?;
+                    let (ids, next) = paging.page(ids).into_parts();
+                    cache.put(&conditional, version, &ids, next)
+                }
+            };
+            Ok(conditional.respond(entry))
+        }
+    };
     let options = match core_options().to_cors() {
         Ok(a) => a,
         Err(a) => return Ok(Err(a)),
@@ -62,9 +117,21 @@ async fn list<'r, 'o: 'r>(db: Db) -> Result<impl Responder<'r, 'o>> {
 }
 
 #[get("/<id>")]
-async fn get_one_bus<'r, 'o: 'r>(db: Db, id: String) -> Result<impl Responder<'r, 'o>> {
-    let out: Json<Busses> = db
-        .run(move |conn| busses::table.filter(busses::placeid.eq(id)).first(conn))
+async fn get_one_bus<'r, 'o: 'r>(
+    db: Db,
+    id: String,
+    languages: Languages,
+) -> Result<impl Responder<'r, 'o>> {
+    let out: Json<LocalizedBusses> = db
+        .run(move |conn| {
+            let busses: Busses = busses::table.filter(busses::placeid.eq(id)).first(conn)?;
+            let names = locale::localize(conn, &languages, &[busses.placeid.clone()])?;
+            let name = names
+                .get(&busses.placeid)
+                .unwrap_or(&busses.placeid)
+                .clone();
+            Ok::<_, diesel::result::Error>(LocalizedBusses { busses, name })
+        })
         .await
         .map(Json)?;
 
@@ -76,16 +143,34 @@ async fn get_one_bus<'r, 'o: 'r>(db: Db, id: String) -> Result<impl Responder<'r
 }
 
 #[delete("/<id>")]
-async fn delete_one_bus<'r, 'o: 'r>(db: Db, id: String) -> Result<impl Responder<'r, 'o>> {
-    let out: usize = db
-        .run(move |conn| {
-            diesel::delete(busses::table)
-                .filter(busses::busid.eq(id))
-                .execute(conn)
+async fn delete_one_bus<'r, 'o: 'r>(
+    _live: LiveEdit,
+    db: Db,
+    actor: Actor,
+    id: String,
+) -> Result<impl Responder<'r, 'o>> {
+    let out: Option<()> = db
+        .write(move |conn| {
+            conn.transaction::<_, diesel::result::Error, _>(|conn| {
+                let bus: Option<Busses> = busses::table
+                    .filter(busses::placeid.eq(&id))
+                    .first(conn)
+                    .optional()?;
+                let bus = match bus {
+                    Some(bus) => bus,
+                    None => return Ok(None),
+                };
+                diesel::delete(busses::table)
+                    .filter(busses::placeid.eq(&id))
+                    .execute(conn)?;
+                Change::new("delete", "busses", &id)
+                    .before(&bus)
+                    .record(conn, &actor)?;
+                Ok(Some(()))
+            })
         })
         .await?;
 
-    let out = (out == 1).then_some(());
     let options = match core_options().to_cors() {
         Ok(a) => a,
         Err(a) => return Ok(Err(a)),
@@ -94,15 +179,13 @@ async fn delete_one_bus<'r, 'o: 'r>(db: Db, id: String) -> Result<impl Responder
 }
 
 async fn run_migrations(rocket: Rocket<Build>) -> Rocket<Build> {
-    use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
-
-    const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
+    use diesel_migrations::MigrationHarness;
 
     Db::get_one(&rocket)
         .await
         .expect("database connection")
         .run(|conn| {
-            conn.run_pending_migrations(MIGRATIONS)
+            conn.run_pending_migrations(db::MIGRATIONS)
                 .expect("diesel migrations");
         })
         .await;
@@ -113,7 +196,6 @@ async fn run_migrations(rocket: Rocket<Build>) -> Rocket<Build> {
 pub fn busses_data() -> AdHoc {
     AdHoc::on_ignite("Data related to busses", |rocket| async {
         rocket
-            .attach(Db::fairing())
             .attach(AdHoc::on_ignite("Diesel Migrations", run_migrations))
             .mount("/busses", routes![list, get_one_bus, delete_one_bus])
     })
@@ -0,0 +1,335 @@
+//! Conditional GETs and a server-side cache for the lists that change rarely
+//! but are fetched constantly. A single data version covers places, routes
+//! and busses: any successful write outside `/bus` bumps it and empties the
+//! cache, so a cached response is never older than the last write.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::hash::{Hash, Hasher};
+use std::io::Cursor;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rocket::fairing::{AdHoc, Fairing, Info, Kind};
+use rocket::http::{ContentType, Method, Status};
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::response::{self, Responder, Response};
+use rocket::serde::{json, Serialize};
+
+use crate::paging;
+
+/// Responses kept at most; the cache starts over when it fills up.
+const CAPACITY: usize = 1024;
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() as i64)
+}
+
+/// Formats unix seconds as an HTTP date, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`.
+fn http_date(secs: i64) -> String {
+    const DAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let (days, rem) = (secs.div_euclid(86_400), secs.rem_euclid(86_400));
+    // Civil date from days since the epoch, after Howard Hinnant.
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+        DAYS[days.rem_euclid(7) as usize],
+        day,
+        MONTHS[(month - 1) as usize],
+        year,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+/// A serialized response, ready to be served again.
+#[derive(Debug)]
+pub struct Entry {
+    body: String,
+    link: Option<String>,
+    etag: String,
+    modified: i64,
+}
+
+#[derive(Debug)]
+struct Inner {
+    version: AtomicU64,
+    modified: AtomicI64,
+    entries: RwLock<HashMap<String, Arc<Entry>>>,
+}
+
+/// Managed state holding the data version and the cached responses. Cloning
+/// shares the same cache.
+#[derive(Debug, Clone)]
+pub struct ResponseCache {
+    inner: Arc<Inner>,
+}
+
+impl Default for ResponseCache {
+    fn default() -> Self {
+        ResponseCache {
+            inner: Arc::new(Inner {
+                version: AtomicU64::new(0),
+                modified: AtomicI64::new(now()),
+                entries: RwLock::new(HashMap::new()),
+            }),
+        }
+    }
+}
+
+impl ResponseCache {
+    /// The current data version. Read it before querying, and hand it to
+    /// [`ResponseCache::put`] with the result.
+    pub fn version(&self) -> u64 {
+        self.inner.version.load(Ordering::SeqCst)
+    }
+
+    /// Marks the data as changed and drops every cached response.
+    pub fn bump(&self) {
+        let mut entries = self.inner.entries.write().unwrap();
+        self.inner.version.fetch_add(1, Ordering::SeqCst);
+        self.inner.modified.store(now(), Ordering::SeqCst);
+        entries.clear();
+    }
+
+    pub fn get(&self, request: &Conditional) -> Option<Arc<Entry>> {
+        self.inner
+            .entries
+            .read()
+            .unwrap()
+            .get(&request.key)
+            .cloned()
+    }
+
+    /// Serializes `value` as the response to `request`, with a `Link` to the
+    /// page starting at `next` if there is one, and caches it unless a write
+    /// happened since `version` was read.
+    pub fn put<T: Serialize>(
+        &self,
+        request: &Conditional,
+        version: u64,
+        value: &T,
+        next: Option<i64>,
+    ) -> Arc<Entry> {
+        let body = json::to_string(value).expect("JSON response");
+        let mut hasher = DefaultHasher::new();
+        body.hash(&mut hasher);
+        let entry = Arc::new(Entry {
+            etag: format!("\"{:016x}\"", hasher.finish()),
+            link: next.map(|next| paging::next_link(&request.path, request.query.as_deref(), next)),
+            modified: self.inner.modified.load(Ordering::SeqCst),
+            body,
+        });
+        let mut entries = self.inner.entries.write().unwrap();
+        if self.version() == version {
+            if entries.len() >= CAPACITY {
+                entries.clear();
+            }
+            entries.insert(request.key.clone(), entry.clone());
+        }
+        entry
+    }
+}
+
+/// Bumps the data version after every successful write. Position updates
+/// under `/bus` don't touch anything cached and are left out.
+#[rocket::async_trait]
+impl Fairing for ResponseCache {
+    fn info(&self) -> Info {
+        Info {
+            name: "Response cache invalidation",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+        let writes = !matches!(req.method(), Method::Get | Method::Head | Method::Options);
+        let positions = req.uri().path().segments().next() == Some("bus");
+        if writes && !positions && res.status().class().is_success() {
+            self.bump();
+        }
+    }
+}
+
+/// What a cached response is looked up by: the request URI and the
+/// `Accept-Language` it was negotiated for, plus whatever a handler adds with
+/// [`Conditional::vary`]. Also carries the client's `If-None-Match`.
+#[derive(Debug, Clone)]
+pub struct Conditional {
+    key: String,
+    path: String,
+    query: Option<String>,
+    if_none_match: Option<String>,
+}
+
+impl Conditional {
+    /// Tells apart responses that depend on more than the request itself.
+    pub fn vary(mut self, part: &str) -> Self {
+        self.key.push('\n');
+        self.key.push_str(part);
+        self
+    }
+
+    /// Whether the client already holds `entry`.
+    fn fresh(&self, entry: &Entry) -> bool {
+        self.if_none_match.as_deref().is_some_and(|tags| {
+            tags.split(',')
+                .map(str::trim)
+                .any(|tag| tag == "*" || tag.strip_prefix("W/").unwrap_or(tag) == entry.etag)
+        })
+    }
+
+    /// The response to serve: the entry itself, or 304 if the client has it.
+    pub fn respond(&self, entry: Arc<Entry>) -> Cached {
+        Cached {
+            fresh: self.fresh(&entry),
+            entry,
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Conditional {
+    type Error = Infallible;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Infallible> {
+        let headers = req.headers();
+        let uri = req.uri();
+        Outcome::Success(Conditional {
+            key: format!(
+                "{}\n{}",
+                uri,
+                headers.get_one("Accept-Language").unwrap_or_default()
+            ),
+            path: uri.path().to_string(),
+            query: uri.query().map(|query| query.to_string()),
+            if_none_match: headers.get_one("If-None-Match").map(str::to_owned),
+        })
+    }
+}
+
+/// A cached JSON response with `ETag` and `Last-Modified`, or an empty 304.
+#[derive(Debug)]
+pub struct Cached {
+    entry: Arc<Entry>,
+    fresh: bool,
+}
+
+impl<'r> Responder<'r, 'static> for Cached {
+    fn respond_to(self, _: &'r Request<'_>) -> response::Result<'static> {
+        let mut response = Response::build();
+        response
+            .raw_header("ETag", self.entry.etag.clone())
+            .raw_header("Last-Modified", http_date(self.entry.modified))
+            .raw_header("Vary", "Accept-Language");
+        if self.fresh {
+            response.status(Status::NotModified);
+        } else {
+            if let Some(link) = &self.entry.link {
+                response.raw_header("Link", link.clone());
+            }
+            response
+                .header(ContentType::JSON)
+                .sized_body(self.entry.body.len(), Cursor::new(self.entry.body.clone()));
+        }
+        response.ok()
+    }
+}
+
+pub fn caching() -> AdHoc {
+    AdHoc::on_ignite("Response cache", |rocket| async {
+        let cache = ResponseCache::default();
+        rocket.manage(cache.clone()).attach(cache)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn conditional(if_none_match: Option<&str>) -> Conditional {
+        Conditional {
+            key: "/place\n".to_owned(),
+            path: "/place".to_owned(),
+            query: None,
+            if_none_match: if_none_match.map(str::to_owned),
+        }
+    }
+
+    #[test]
+    fn http_date_formats_unix_seconds() {
+        assert_eq!(http_date(0), "Thu, 01 Jan 1970 00:00:00 GMT");
+        assert_eq!(http_date(784_111_777), "Sun, 06 Nov 1994 08:49:37 GMT");
+        assert_eq!(http_date(951_782_400), "Tue, 29 Feb 2000 00:00:00 GMT");
+    }
+
+    #[test]
+    fn put_caches_until_bumped() {
+        let cache = ResponseCache::default();
+        let request = conditional(None);
+        let version = cache.version();
+        cache.put(&request, version, &["shimla"], None);
+        assert_eq!(cache.get(&request).unwrap().body, r#"["shimla"]"#);
+        cache.bump();
+        assert!(cache.get(&request).is_none());
+    }
+
+    #[test]
+    fn put_skips_results_read_before_a_write() {
+        let cache = ResponseCache::default();
+        let request = conditional(None);
+        let version = cache.version();
+        cache.bump();
+        let entry = cache.put(&request, version, &["shimla"], None);
+        assert_eq!(entry.body, r#"["shimla"]"#);
+        assert!(cache.get(&request).is_none());
+    }
+
+    #[test]
+    fn etag_follows_the_body() {
+        let cache = ResponseCache::default();
+        let request = conditional(None);
+        let a = cache.put(&request, cache.version(), &["shimla"], None);
+        let b = cache.put(&request, cache.version(), &["shimla"], None);
+        let c = cache.put(&request, cache.version(), &["mandi"], None);
+        assert_eq!(a.etag, b.etag);
+        assert_ne!(a.etag, c.etag);
+    }
+
+    #[test]
+    fn if_none_match_makes_a_response_fresh() {
+        let cache = ResponseCache::default();
+        let entry = cache.put(&conditional(None), cache.version(), &["shimla"], None);
+        let etag = entry.etag.clone();
+        assert!(!conditional(None).respond(entry.clone()).fresh);
+        assert!(conditional(Some(&etag)).respond(entry.clone()).fresh);
+        assert!(
+            conditional(Some(&format!("W/{etag}")))
+                .respond(entry.clone())
+                .fresh
+        );
+        assert!(
+            conditional(Some(&format!("\"other\", {etag}")))
+                .respond(entry.clone())
+                .fresh
+        );
+        assert!(conditional(Some("*")).respond(entry.clone()).fresh);
+        assert!(!conditional(Some("\"other\"")).respond(entry).fresh);
+    }
+}
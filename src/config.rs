@@ -0,0 +1,30 @@
+//! Sections of `Rocket.toml`. A section that is left out keeps its defaults;
+//! one that is there but doesn't parse keeps the server from starting, rather
+//! than running with settings nobody asked for.
+
+use rocket::figment::{self, Figment};
+use rocket::serde::DeserializeOwned;
+use rocket::{Build, Rocket};
+
+/// Section `name` of `figment`, or its defaults when it is left out.
+pub fn section<T: DeserializeOwned + Default>(
+    figment: &Figment,
+    name: &str,
+) -> Result<T, figment::Error> {
+    match figment.find_value(name) {
+        Err(error) if error.missing() => Ok(T::default()),
+        _ => figment.extract_inner(name),
+    }
+}
+
+/// Section `name` of the configuration `rocket` ignites with, logging why
+/// when it can't be read.
+pub fn read<T: DeserializeOwned + Default>(rocket: &Rocket<Build>, name: &str) -> Option<T> {
+    match section(rocket.figment(), name) {
+        Ok(config) => Some(config),
+        Err(error) => {
+            error!("[{}]: {}", name, error);
+            None
+        }
+    }
+}
@@ -0,0 +1,280 @@
+//! The database backend, picked at build time. SQLite is the default; build
+//! with `--no-default-features --features postgres` to run on PostgreSQL
+//! with PostGIS instead. Both speak the same HTTP API and keep their own
+//! migrations, in `migrations/` and `migrations_postgres/`.
+//!
+//! Every module draws its connections from one pool, opened by [`pool`], and
+//! writes through [`Db::write`] so that they queue as `[sqlite]` configures.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use diesel_migrations::{embed_migrations, EmbeddedMigrations};
+use rocket::fairing::AdHoc;
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::serde::Deserialize;
+use rocket::tokio::sync::{OwnedRwLockReadGuard, OwnedRwLockWriteGuard, RwLock};
+use rocket::tokio::task::spawn_blocking;
+use rocket::{Phase, Rocket};
+use rocket_sync_db_pools::diesel;
+
+use self::diesel::connection::SimpleConnection;
+use self::diesel::r2d2::{self, ConnectionManager, CustomizeConnection, PooledConnection};
+
+use crate::config;
+
+#[cfg(all(feature = "sqlite", feature = "postgres"))]
+compile_error!("features `sqlite` and `postgres` are mutually exclusive");
+
+#[cfg(feature = "postgres")]
+pub type Connection = diesel::PgConnection;
+
+#[cfg(not(feature = "postgres"))]
+pub type Connection = diesel::SqliteConnection;
+
+#[cfg(feature = "postgres")]
+pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations_postgres");
+
+#[cfg(not(feature = "postgres"))]
+pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
+
+/// How SQLite keeps its journal; see `PRAGMA journal_mode`.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(crate = "rocket::serde", rename_all = "lowercase")]
+pub enum JournalMode {
+    Delete,
+    Truncate,
+    Persist,
+    Memory,
+    Wal,
+    Off,
+}
+
+/// How often SQLite waits for writes to reach the disk; see
+/// `PRAGMA synchronous`.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(crate = "rocket::serde", rename_all = "lowercase")]
+pub enum Synchronous {
+    Off,
+    Normal,
+    Full,
+    Extra,
+}
+
+/// The `[sqlite]` section of `Rocket.toml`. Ignored on PostgreSQL.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct SqliteConfig {
+    pub journal_mode: JournalMode,
+    pub synchronous: Synchronous,
+    /// Milliseconds a connection waits for a lock before failing with
+    /// `database is locked`.
+    pub busy_timeout: u32,
+    /// Pages when positive, KiB when negative.
+    pub cache_size: i64,
+    /// Whether writes wait for each other in the server instead of racing
+    /// for the database lock.
+    pub serialize_writes: bool,
+}
+
+impl Default for SqliteConfig {
+    fn default() -> Self {
+        SqliteConfig {
+            journal_mode: JournalMode::Wal,
+            synchronous: Synchronous::Normal,
+            busy_timeout: 5000,
+            cache_size: -16000,
+            serialize_writes: true,
+        }
+    }
+}
+
+impl SqliteConfig {
+    #[cfg(not(feature = "postgres"))]
+    fn pragmas(&self) -> String {
+        format!(
+            "PRAGMA journal_mode = {:?}; PRAGMA synchronous = {:?}; \
+             PRAGMA busy_timeout = {}; PRAGMA cache_size = {};",
+            self.journal_mode, self.synchronous, self.busy_timeout, self.cache_size
+        )
+    }
+}
+
+/// Runs the configured pragmas on every connection the pool opens.
+#[derive(Debug)]
+struct Tune(String);
+
+impl CustomizeConnection<Connection, r2d2::Error> for Tune {
+    fn on_acquire(&self, conn: &mut Connection) -> Result<(), r2d2::Error> {
+        if self.0.is_empty() {
+            return Ok(());
+        }
+        conn.batch_execute(&self.0).map_err(r2d2::Error::QueryError)
+    }
+}
+
+type Pool = r2d2::Pool<ConnectionManager<Connection>>;
+
+/// Managed state for writers: a first-come, first-served queue that lets one
+/// write at a time at the database, or every write at once when
+/// `serialize_writes` is off. Restores and imports take the whole database
+/// for themselves either way. Cloning shares the same queue.
+#[derive(Debug, Clone)]
+pub struct Writes {
+    queue: Arc<RwLock<()>>,
+    serialize: bool,
+}
+
+/// A writer's place at the database; it lasts until dropped.
+#[derive(Debug)]
+pub struct Turn {
+    _shared: Option<OwnedRwLockReadGuard<()>>,
+    _exclusive: Option<OwnedRwLockWriteGuard<()>>,
+}
+
+impl Writes {
+    #[cfg(feature = "postgres")]
+    fn new(_: SqliteConfig) -> Self {
+        Writes {
+            queue: Arc::default(),
+            serialize: false,
+        }
+    }
+
+    #[cfg(not(feature = "postgres"))]
+    fn new(config: SqliteConfig) -> Self {
+        Writes {
+            queue: Arc::default(),
+            serialize: config.serialize_writes,
+        }
+    }
+
+    /// Waits for this write's turn.
+    pub async fn turn(&self) -> Turn {
+        if self.serialize {
+            return self.exclusive().await;
+        }
+        Turn {
+            _shared: Some(self.queue.clone().read_owned().await),
+            _exclusive: None,
+        }
+    }
+
+    /// Waits until no other write is running and keeps them all out until
+    /// the turn is dropped.
+    pub async fn exclusive(&self) -> Turn {
+        Turn {
+            _shared: None,
+            _exclusive: Some(self.queue.clone().write_owned().await),
+        }
+    }
+}
+
+/// A connection from the shared pool: a request guard, failing with 503 when
+/// none frees up within the pool's `timeout`, or [`Db::get_one`] outside of
+/// requests.
+#[derive(Clone)]
+pub struct Db {
+    conn: Arc<std::sync::Mutex<PooledConnection<ConnectionManager<Connection>>>>,
+    writes: Writes,
+}
+
+impl Db {
+    pub async fn get_one<P: Phase>(rocket: &Rocket<P>) -> Option<Db> {
+        let pool = rocket.state::<Pool>()?.clone();
+        let writes = rocket.state::<Writes>()?.clone();
+        match spawn_blocking(move || pool.get()).await {
+            Ok(Ok(conn)) => Some(Db {
+                conn: Arc::new(std::sync::Mutex::new(conn)),
+                writes,
+            }),
+            Ok(Err(e)) => {
+                error!("database connection: {}", e);
+                None
+            }
+            Err(_) => None,
+        }
+    }
+
+    /// Runs `f` on the connection on a thread that may block.
+    pub async fn run<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut Connection) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let conn = self.conn.clone();
+        spawn_blocking(move || {
+            let mut conn = conn.lock().expect("database connection");
+            f(&mut conn)
+        })
+        .await
+        .expect("database task")
+    }
+
+    /// Runs `f` like [`Db::run`] once it is this write's turn.
+    pub async fn write<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut Connection) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let _turn = self.writes.turn().await;
+        self.run(f).await
+    }
+
+    /// Waits until no other write is running, for work that replaces the
+    /// database wholesale; see [`Writes::exclusive`].
+    pub async fn exclusive(&self) -> Turn {
+        self.writes.exclusive().await
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Db {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, ()> {
+        match Db::get_one(req.rocket()).await {
+            Some(db) => Outcome::Success(db),
+            None => Outcome::Failure((Status::ServiceUnavailable, ())),
+        }
+    }
+}
+
+/// Opens the pool configured in `[databases.diesel]`, tuned as `[sqlite]`
+/// says, and the write queue.
+pub fn pool() -> AdHoc {
+    AdHoc::try_on_ignite("Database pool", |rocket| async {
+        let config: SqliteConfig = match config::read(&rocket, "sqlite") {
+            Some(config) => config,
+            None => return Err(rocket),
+        };
+        let database = match rocket_sync_db_pools::Config::from("diesel", &rocket) {
+            Ok(database) => database,
+            Err(e) => {
+                error!("[databases.diesel]: {}", e);
+                return Err(rocket);
+            }
+        };
+        #[cfg(feature = "postgres")]
+        let pragmas = String::new();
+        #[cfg(not(feature = "postgres"))]
+        let pragmas = config.pragmas();
+        let built = spawn_blocking(move || {
+            Pool::builder()
+                .max_size(database.pool_size)
+                .connection_timeout(Duration::from_secs(database.timeout.into()))
+                .connection_customizer(Box::new(Tune(pragmas)))
+                .build(ConnectionManager::new(database.url))
+        })
+        .await;
+        match built {
+            Ok(Ok(pool)) => Ok(rocket.manage(pool).manage(Writes::new(config))),
+            Ok(Err(e)) => {
+                error!("database pool: {}", e);
+                Err(rocket)
+            }
+            Err(_) => Err(rocket),
+        }
+    })
+}
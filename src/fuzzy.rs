@@ -0,0 +1,132 @@
+//! Forgiving name matching for place search. Names are compared lowercased
+//! with runs of whitespace and punctuation collapsed, so "ner-chowk" and
+//! "Ner  Chowk" are the same name.
+
+/// How well a name matches a query; lower ranks sort first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Rank {
+    Exact,
+    Prefix,
+    WordPrefix,
+    Contains,
+    /// Within this many edits of the name or one of its trailing words, or of
+    /// their start when the query is still being typed.
+    Typo(usize),
+}
+
+/// Lowercases `name` and turns every run of whitespace and ASCII punctuation
+/// into a single space. Other characters are kept, so combining marks in
+/// Devanagari names survive.
+pub fn normalize(name: &str) -> String {
+    name.split(|c: char| c.is_whitespace() || c.is_ascii_punctuation())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Edit distance counting insertions, deletions, substitutions and swaps of
+/// adjacent characters as one edit each.
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let (a, b): (Vec<char>, Vec<char>) = (a.chars().collect(), b.chars().collect());
+    let mut rows = vec![(0..=b.len()).collect::<Vec<_>>()];
+    for i in 1..=a.len() {
+        let mut row = vec![i; b.len() + 1];
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            row[j] = (rows[i - 1][j] + 1)
+                .min(row[j - 1] + 1)
+                .min(rows[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                row[j] = row[j].min(rows[i - 2][j - 2] + 1);
+            }
+        }
+        rows.push(row);
+    }
+    rows[a.len()][b.len()]
+}
+
+/// Edits tolerated for a query of `len` characters: none for very short
+/// queries, then one per four characters.
+fn tolerance(len: usize) -> usize {
+    match len {
+        0..=2 => 0,
+        _ => (len / 4).max(1),
+    }
+}
+
+/// Ranks `name` against an already normalized `query`, or `None` when it is
+/// too far off to suggest.
+pub fn rank(query: &str, name: &str) -> Option<Rank> {
+    let name = normalize(name);
+    if query.is_empty() {
+        return None;
+    }
+    if name == query {
+        return Some(Rank::Exact);
+    }
+    if name.starts_with(query) {
+        return Some(Rank::Prefix);
+    }
+    if name.split(' ').any(|word| word.starts_with(query)) {
+        return Some(Rank::WordPrefix);
+    }
+    if name.contains(query) {
+        return Some(Rank::Contains);
+    }
+    let len = query.chars().count();
+    let edits = name
+        .match_indices(' ')
+        .map(|(i, _)| &name[i + 1..])
+        .chain([name.as_str()])
+        .map(|tail| {
+            let typed: String = tail.chars().take(len).collect();
+            edit_distance(query, tail).min(edit_distance(query, &typed))
+        })
+        .min()?;
+    (edits <= tolerance(len)).then_some(Rank::Typo(edits))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_collapses_case_and_punctuation() {
+        assert_eq!(normalize("  Ner--Chowk!! "), "ner chowk");
+        assert_eq!(normalize("Ner  Chowk"), "ner chowk");
+        assert_eq!(normalize("..."), "");
+    }
+
+    #[test]
+    fn edit_distance_counts_swaps_as_one_edit() {
+        assert_eq!(edit_distance("mandi", "mandi"), 0);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("shmila", "shimla"), 1);
+        assert_eq!(edit_distance("ab", "ba"), 1);
+    }
+
+    #[test]
+    fn rank_orders_kinds_of_match() {
+        assert_eq!(rank("shimla", "Shimla"), Some(Rank::Exact));
+        assert_eq!(rank("shim", "Shimla"), Some(Rank::Prefix));
+        assert_eq!(rank("chowk", "Ner Chowk"), Some(Rank::WordPrefix));
+        assert_eq!(rank("derna", "Sundernagar"), Some(Rank::Contains));
+        assert!(Rank::Contains < Rank::Typo(0));
+        assert!(Rank::Typo(1) < Rank::Typo(2));
+    }
+
+    #[test]
+    fn rank_tolerates_typos_by_query_length() {
+        assert_eq!(rank("shmila", "Shimla"), Some(Rank::Typo(1)));
+        assert_eq!(rank("mandu", "Mandi"), Some(Rank::Typo(1)));
+        // Still being typed: compared with the start of the name.
+        assert_eq!(rank("sundr", "Sundernagar"), Some(Rank::Typo(1)));
+        // Trailing words count on their own.
+        assert_eq!(rank("chowck", "Ner Chowk"), Some(Rank::Typo(1)));
+        assert_eq!(rank("mx", "Mandi"), None);
+        assert_eq!(rank("xyz", "Mandi"), None);
+        assert_eq!(rank("", "Mandi"), None);
+    }
+}
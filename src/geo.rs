@@ -0,0 +1,196 @@
+//! Small spherical-earth helpers shared by the position and route modules.
+//! Coordinates are `(latitude, longitude)` pairs in degrees, as stored in the
+//! `*_location` tables.
+
+const EARTH_RADIUS_M: f64 = 6_371_008.8;
+
+/// Great-circle distance between two points, in meters.
+pub fn distance(from: (f32, f32), to: (f32, f32)) -> f32 {
+    let (lat1, lon1) = (
+        f64::from(from.0).to_radians(),
+        f64::from(from.1).to_radians(),
+    );
+    let (lat2, lon2) = (f64::from(to.0).to_radians(), f64::from(to.1).to_radians());
+    let a = ((lat2 - lat1) / 2.0).sin().powi(2)
+        + lat1.cos() * lat2.cos() * ((lon2 - lon1) / 2.0).sin().powi(2);
+    (2.0 * EARTH_RADIUS_M * a.sqrt().asin()) as f32
+}
+
+/// Initial bearing from `from` towards `to`, in degrees clockwise from north.
+pub fn bearing(from: (f32, f32), to: (f32, f32)) -> f32 {
+    let (lat1, lon1) = (
+        f64::from(from.0).to_radians(),
+        f64::from(from.1).to_radians(),
+    );
+    let (lat2, lon2) = (f64::from(to.0).to_radians(), f64::from(to.1).to_radians());
+    let y = (lon2 - lon1).sin() * lat2.cos();
+    let x = lat1.cos() * lat2.sin() - lat1.sin() * lat2.cos() * (lon2 - lon1).cos();
+    (y.atan2(x).to_degrees().rem_euclid(360.0)) as f32
+}
+
+/// Where a point lands when projected onto a polyline.
+#[derive(Debug, Clone, Copy)]
+pub struct Projection {
+    /// The closest point on the polyline.
+    pub point: (f32, f32),
+    /// Distance from the start of the polyline to `point`, in meters.
+    pub along: f32,
+}
+
+/// Projects `point` onto the closest segment of `polyline`. Segments are short
+/// enough for an equirectangular approximation around their start point.
+pub fn project(polyline: &[(f32, f32)], point: (f32, f32)) -> Option<Projection> {
+    let first = *polyline.first()?;
+    let mut best = Projection {
+        point: first,
+        along: 0.0,
+    };
+    let mut best_offset = distance(point, first);
+    let mut travelled = 0.0;
+    for pair in polyline.windows(2) {
+        let (a, b) = (pair[0], pair[1]);
+        let scale = f64::from(a.0).to_radians().cos() as f32;
+        let (bx, by) = ((b.1 - a.1) * scale, b.0 - a.0);
+        let (px, py) = ((point.1 - a.1) * scale, point.0 - a.0);
+        let length2 = bx * bx + by * by;
+        let t = if length2 > 0.0 {
+            ((px * bx + py * by) / length2).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        let snapped = (a.0 + t * (b.0 - a.0), a.1 + t * (b.1 - a.1));
+        let segment = distance(a, b);
+        let offset = distance(point, snapped);
+        if offset < best_offset {
+            best_offset = offset;
+            best = Projection {
+                point: snapped,
+                along: travelled + t * segment,
+            };
+        }
+        travelled += segment;
+    }
+    Some(best)
+}
+
+/// Parses a stored path of `lat,lon` pairs joined by `|`, skipping malformed
+/// points.
+pub fn decode_path(path: &str) -> Vec<(f32, f32)> {
+    path.split('|')
+        .filter_map(|point| {
+            let (lat, lon) = point.split_once(',')?;
+            Some((lat.trim().parse().ok()?, lon.trim().parse().ok()?))
+        })
+        .collect()
+}
+
+/// Inverse of [`decode_path`].
+pub fn encode_path(points: &[(f32, f32)]) -> String {
+    points
+        .iter()
+        .map(|(lat, lon)| format!("{lat},{lon}"))
+        .collect::<Vec<_>>()
+        .join("|")
+}
+
+/// Decodes an encoded polyline (the Google polyline algorithm, five decimal
+/// places) into `(lat, lon)` points. Returns `None` on truncated input.
+pub fn decode_polyline(encoded: &str) -> Option<Vec<(f32, f32)>> {
+    let mut bytes = encoded.bytes();
+    let mut next = || -> Option<Option<i64>> {
+        let mut result = 0i64;
+        let mut shift = 0;
+        loop {
+            let byte = match bytes.next() {
+                Some(byte) => i64::from(byte) - 63,
+                None if shift == 0 => return Some(None),
+                None => return None,
+            };
+            if !(0..64).contains(&byte) || shift > 60 {
+                return None;
+            }
+            result |= (byte & 0x1f) << shift;
+            shift += 5;
+            if byte < 0x20 {
+                break;
+            }
+        }
+        Some(Some(if result & 1 == 1 {
+            !(result >> 1)
+        } else {
+            result >> 1
+        }))
+    };
+    let (mut lat, mut lon) = (0i64, 0i64);
+    let mut points = vec![];
+    while let Some(dlat) = next()? {
+        lat += dlat;
+        lon += next()??;
+        points.push((lat as f32 / 1e5, lon as f32 / 1e5));
+    }
+    Some(points)
+}
+
+/// Inverse of [`decode_polyline`].
+pub fn encode_polyline(points: &[(f32, f32)]) -> String {
+    fn push(out: &mut String, delta: i64) {
+        let mut value = if delta < 0 { !(delta << 1) } else { delta << 1 };
+        while value >= 0x20 {
+            out.push(char::from((0x20 | (value & 0x1f)) as u8 + 63));
+            value >>= 5;
+        }
+        out.push(char::from(value as u8 + 63));
+    }
+    let mut out = String::new();
+    let (mut lat, mut lon) = (0i64, 0i64);
+    for (next_lat, next_lon) in points {
+        let (next_lat, next_lon) = (
+            (f64::from(*next_lat) * 1e5).round() as i64,
+            (f64::from(*next_lon) * 1e5).round() as i64,
+        );
+        push(&mut out, next_lat - lat);
+        push(&mut out, next_lon - lon);
+        (lat, lon) = (next_lat, next_lon);
+    }
+    out
+}
+
+/// Ground resolution of a web-mercator map tile at `zoom`, in meters per
+/// pixel at `latitude`.
+pub fn meters_per_pixel(zoom: u8, latitude: f32) -> f32 {
+    (156_543.03 * f64::from(latitude).to_radians().cos() / 2f64.powi(i32::from(zoom))) as f32
+}
+
+/// Douglas-Peucker simplification: drops points that lie within `tolerance`
+/// meters of the line through their neighbours. The end points are kept.
+pub fn simplify(points: &[(f32, f32)], tolerance: f32) -> Vec<(f32, f32)> {
+    fn offset(point: (f32, f32), a: (f32, f32), b: (f32, f32)) -> f32 {
+        match project(&[a, b], point) {
+            Some(projection) => distance(point, projection.point),
+            None => 0.0,
+        }
+    }
+    fn keep(points: &[(f32, f32)], tolerance: f32, kept: &mut Vec<(f32, f32)>) {
+        let (first, last) = (points[0], points[points.len() - 1]);
+        let farthest = points[1..points.len() - 1]
+            .iter()
+            .enumerate()
+            .map(|(i, point)| (i + 1, offset(*point, first, last)))
+            .max_by(|a, b| a.1.total_cmp(&b.1));
+        match farthest {
+            Some((i, offset)) if offset > tolerance => {
+                keep(&points[..=i], tolerance, kept);
+                keep(&points[i..], tolerance, kept);
+            }
+            _ => kept.push(last),
+        }
+    }
+    match points {
+        [] | [_] | [_, _] => points.to_vec(),
+        _ => {
+            let mut kept = vec![points[0]];
+            keep(points, tolerance, &mut kept);
+            kept
+        }
+    }
+}
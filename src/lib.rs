@@ -0,0 +1,93 @@
+#[macro_use]
+extern crate rocket;
+
+use busses::busses_data;
+
+use rocket::fairing::AdHoc;
+use rocket::figment::Provider;
+use rocket::{Build, Rocket};
+
+use rocket_sync_db_pools::diesel;
+
+mod admin;
+mod audit;
+mod backup;
+mod busses;
+mod cache;
+mod config;
+mod db;
+mod fuzzy;
+mod geo;
+mod limit;
+mod live;
+mod locale;
+mod paging;
+mod places;
+mod retention;
+mod revisions;
+mod routes;
+mod viewport;
+use places::place_data;
+use routes::route_data;
+use viewport::viewport_data;
+mod bus;
+pub use backup::command;
+use bus::bus_data;
+use rocket::http;
+use rocket_cors::{AllowedHeaders, AllowedOrigins, Cors, Method};
+
+fn cors() -> Cors {
+    rocket_cors::CorsOptions {
+        allowed_origins: AllowedOrigins::all(),
+        allowed_methods: vec![
+            http::Method::Get,
+            http::Method::Post,
+            http::Method::Options,
+            http::Method::Delete,
+            http::Method::Put,
+            http::Method::Patch,
+        ]
+        .into_iter()
+        .map(Method)
+        .collect(),
+        allowed_headers: AllowedHeaders::all(),
+        allow_credentials: true,
+        fairing_route_base: "/".to_owned(),
+        max_age: Some(42),
+        ..Default::default()
+    }
+    .to_cors()
+    .unwrap()
+}
+
+fn stage() -> AdHoc {
+    AdHoc::on_ignite("Rusqlite Stage", |rocket| async {
+        rocket
+            .manage(cors())
+            .mount("/", rocket_cors::catch_all_options_routes())
+    })
+}
+
+/// The server, configured by `Rocket.toml` and `ROCKET_` variables.
+pub fn rocket() -> Rocket<Build> {
+    custom(rocket::Config::figment())
+}
+
+/// The server, configured by `provider` alone; benches pass a figment built
+/// on `rocket::Config::figment()` to point it elsewhere.
+pub fn custom<T: Provider>(provider: T) -> Rocket<Build> {
+    rocket::custom(provider)
+        .attach(stage())
+        .attach(admin::admin())
+        .attach(db::pool())
+        .attach(cache::caching())
+        .attach(limit::rate_limiting())
+        .attach(bus_data())
+        .attach(route_data())
+        .attach(place_data())
+        .attach(busses_data())
+        .attach(viewport_data())
+        .attach(audit::audit_data())
+        .attach(revisions::revision_data())
+        .attach(backup::backup_data())
+}
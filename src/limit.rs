@@ -0,0 +1,358 @@
+//! Token-bucket rate limiting. Requests fall into the first group in
+//! `[rate_limit]` whose path and methods match, and each client gets its own
+//! bucket per group: by `X-Api-Key` when it sends one of the configured
+//! `api_keys`, else by the bus it reports for in groups keyed `by_bus`, else
+//! by IP address. Trackers behind carrier NAT share addresses, so ingest is
+//! keyed by bus. A request finding its bucket empty is answered 429 with
+//! `Retry-After` before it reaches any handler.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::time::Instant;
+
+use rocket::fairing::{AdHoc, Fairing, Info, Kind};
+use rocket::http::uri::Origin;
+use rocket::http::{Method, Status};
+use rocket::serde::Deserialize;
+use rocket::{Data, Request, Response};
+
+use crate::config;
+
+/// Where limited requests are sent instead of their own route.
+const LIMITED: &str = "/rate-limited";
+
+/// Buckets kept before the least recently used one is dropped for each new
+/// one.
+const MAX_BUCKETS: usize = 100_000;
+
+/// Bytes of a request body searched for a `busid`.
+const PEEK: usize = 512;
+
+/// One `[[rate_limit.groups]]` entry of `Rocket.toml`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Group {
+    pub name: String,
+    /// Path prefix, matched on whole segments.
+    pub path: String,
+    /// Methods the group covers; all of them when empty.
+    #[serde(default)]
+    pub methods: Vec<String>,
+    /// Requests per second a client may keep up.
+    pub rate: f64,
+    /// Requests a client may make at once after being idle.
+    pub burst: f64,
+    /// Whether requests naming a bus, in the path as `/one/<id>` or as the
+    /// `busid` of a posted fix, get a bucket per bus rather than per IP.
+    #[serde(default)]
+    pub by_bus: bool,
+}
+
+impl Group {
+    fn matches(&self, req: &Request<'_>) -> bool {
+        let path = req.uri().path();
+        let prefix = self.path.trim_end_matches('/');
+        let under = path.as_str() == prefix
+            || path
+                .as_str()
+                .strip_prefix(prefix)
+                .is_some_and(|rest| rest.starts_with('/'));
+        let method = self.methods.is_empty()
+            || self
+                .methods
+                .iter()
+                .any(|method| method.eq_ignore_ascii_case(req.method().as_str()));
+        under && method
+    }
+}
+
+/// The `[rate_limit]` section of `Rocket.toml`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct LimitConfig {
+    pub groups: Vec<Group>,
+    /// Keys that get a bucket of their own instead of sharing their IP's.
+    #[serde(default)]
+    pub api_keys: Vec<String>,
+}
+
+impl Default for LimitConfig {
+    fn default() -> Self {
+        LimitConfig {
+            groups: vec![
+                Group {
+                    name: "ingest".to_owned(),
+                    path: "/bus".to_owned(),
+                    methods: Vec::from(["POST", "PUT", "PATCH", "DELETE"].map(str::to_owned)),
+                    rate: 1.0,
+                    burst: 10.0,
+                    by_bus: true,
+                },
+                Group {
+                    name: "reads".to_owned(),
+                    path: "/".to_owned(),
+                    methods: vec!["GET".to_owned()],
+                    rate: 20.0,
+                    burst: 100.0,
+                    by_bus: false,
+                },
+            ],
+            api_keys: vec![],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Seconds a limited request should wait, kept for the response.
+#[derive(Debug, Clone, Copy)]
+struct RetryAfter(Option<u64>);
+
+/// A bucket per group index and client, with the order they were last used
+/// in so that the least recently used one can go first.
+#[derive(Debug, Default)]
+struct Buckets {
+    buckets: HashMap<(usize, String), (Bucket, u64)>,
+    used: BTreeMap<u64, (usize, String)>,
+    uses: u64,
+}
+
+impl Buckets {
+    /// The bucket of `key`, made with `new` if there is none, after dropping
+    /// the least recently used one when there are too many.
+    fn get(&mut self, key: (usize, String), new: impl FnOnce() -> Bucket) -> &mut Bucket {
+        self.uses += 1;
+        let uses = self.uses;
+        match self.buckets.get_mut(&key) {
+            Some((_, used)) => {
+                self.used.remove(&*used);
+                *used = uses;
+            }
+            None => {
+                if self.buckets.len() >= MAX_BUCKETS {
+                    if let Some((_, oldest)) = self.used.pop_first() {
+                        self.buckets.remove(&oldest);
+                    }
+                }
+                self.buckets.insert(key.clone(), (new(), uses));
+            }
+        }
+        self.used.insert(uses, key.clone());
+        &mut self.buckets.get_mut(&key).expect("bucket just used").0
+    }
+}
+
+#[derive(Debug)]
+pub struct RateLimit {
+    config: LimitConfig,
+    buckets: Mutex<Buckets>,
+}
+
+impl RateLimit {
+    pub fn new(config: LimitConfig) -> Self {
+        RateLimit {
+            config,
+            buckets: Mutex::new(Buckets::default()),
+        }
+    }
+
+    /// Takes a token from the bucket of `client` in group `index`, or says
+    /// how many seconds until one is available.
+    fn take(&self, index: usize, client: &str) -> Result<(), u64> {
+        let group = &self.config.groups[index];
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = buckets.get((index, client.to_owned()), || Bucket {
+            tokens: group.burst,
+            updated: now,
+        });
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * group.rate).min(group.burst);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else if group.rate > 0.0 {
+            Err(((1.0 - bucket.tokens) / group.rate).ceil() as u64)
+        } else {
+            Err(u64::MAX)
+        }
+    }
+}
+
+/// The bus a request is about: the id after `one` in its path, as in
+/// `/bus/one/<id>`.
+fn path_busid(req: &Request<'_>) -> Option<String> {
+    let mut segments = req.uri().path().segments();
+    segments.find(|segment| *segment == "one")?;
+    segments.next().map(str::to_owned)
+}
+
+/// The `busid` of a JSON object body, if it starts within the first bytes.
+/// Batches name many buses and have none.
+fn posted_busid(body: &[u8]) -> Option<String> {
+    let body = String::from_utf8_lossy(body);
+    let body = body.trim_start();
+    if !body.starts_with('{') {
+        return None;
+    }
+    let rest = &body[body.find("\"busid\"")? + "\"busid\"".len()..];
+    let rest = rest.trim_start().strip_prefix(':')?.trim_start();
+    let rest = rest.strip_prefix('"')?;
+    Some(rest[..rest.find('"')?].to_owned())
+}
+
+#[rocket::async_trait]
+impl Fairing for RateLimit {
+    fn info(&self) -> Info {
+        Info {
+            name: "Rate limiting",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, req: &mut Request<'_>, data: &mut Data<'_>) {
+        let index = match self
+            .config
+            .groups
+            .iter()
+            .position(|group| group.matches(req))
+        {
+            Some(index) => index,
+            None => return,
+        };
+        let key = req
+            .headers()
+            .get_one("X-Api-Key")
+            .filter(|key| self.config.api_keys.iter().any(|known| known == key));
+        let bus = match (key, self.config.groups[index].by_bus) {
+            (None, true) => match path_busid(req) {
+                Some(busid) => Some(busid),
+                None => posted_busid(data.peek(PEEK).await),
+            },
+            _ => None,
+        };
+        let client = match (key, bus, req.client_ip()) {
+            (Some(key), _, _) => format!("key:{key}"),
+            (None, Some(busid), _) => format!("bus:{busid}"),
+            (None, None, Some(ip)) => format!("ip:{ip}"),
+            (None, None, None) => "unknown".to_owned(),
+        };
+        if let Err(seconds) = self.take(index, &client) {
+            debug!(
+                "{} is over the {} rate limit",
+                client, self.config.groups[index].name
+            );
+            req.local_cache(|| RetryAfter(Some(seconds)));
+            req.set_method(Method::Get);
+            req.set_uri(Origin::parse(LIMITED).expect("valid path"));
+        }
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+        if let RetryAfter(Some(seconds)) = req.local_cache(|| RetryAfter(None)) {
+            res.set_status(Status::TooManyRequests);
+            res.set_raw_header("Retry-After", seconds.to_string());
+        }
+    }
+}
+
+#[get("/rate-limited")]
+fn rate_limited() -> Status {
+    Status::TooManyRequests
+}
+
+pub fn rate_limiting() -> AdHoc {
+    AdHoc::try_on_ignite("Rate limiting", |rocket| async {
+        let config: LimitConfig = match config::read(&rocket, "rate_limit") {
+            Some(config) => config,
+            None => return Err(rocket),
+        };
+        Ok(rocket
+            .attach(RateLimit::new(config))
+            .mount("/", routes![rate_limited]))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limit(rate: f64, burst: f64) -> RateLimit {
+        RateLimit::new(LimitConfig {
+            groups: vec![Group {
+                name: "ingest".to_owned(),
+                path: "/bus".to_owned(),
+                methods: vec![],
+                rate,
+                burst,
+                by_bus: true,
+            }],
+            api_keys: vec![],
+        })
+    }
+
+    #[test]
+    fn bucket_allows_a_burst_then_waits() {
+        let limit = limit(1.0, 2.0);
+        assert_eq!(limit.take(0, "bus:a"), Ok(()));
+        assert_eq!(limit.take(0, "bus:a"), Ok(()));
+        assert_eq!(limit.take(0, "bus:a"), Err(1));
+    }
+
+    #[test]
+    fn buckets_are_per_client() {
+        let limit = limit(1.0, 1.0);
+        assert_eq!(limit.take(0, "bus:a"), Ok(()));
+        assert_eq!(limit.take(0, "bus:a"), Err(1));
+        assert_eq!(limit.take(0, "bus:b"), Ok(()));
+    }
+
+    #[test]
+    fn waits_in_proportion_to_the_rate() {
+        let limit = limit(0.25, 1.0);
+        assert_eq!(limit.take(0, "ip:10.0.0.1"), Ok(()));
+        assert_eq!(limit.take(0, "ip:10.0.0.1"), Err(4));
+    }
+
+    #[test]
+    fn zero_rate_never_refills() {
+        let limit = limit(0.0, 1.0);
+        assert_eq!(limit.take(0, "ip:10.0.0.1"), Ok(()));
+        assert_eq!(limit.take(0, "ip:10.0.0.1"), Err(u64::MAX));
+    }
+
+    #[test]
+    fn least_recently_used_bucket_goes_first() {
+        let mut buckets = Buckets::default();
+        let new = || Bucket {
+            tokens: 1.0,
+            updated: Instant::now(),
+        };
+        for n in 0..MAX_BUCKETS {
+            buckets.get((0, n.to_string()), new);
+        }
+        buckets.get((0, "0".to_owned()), new);
+        buckets.get((0, "new".to_owned()), new);
+        assert_eq!(buckets.buckets.len(), MAX_BUCKETS);
+        assert_eq!(buckets.used.len(), MAX_BUCKETS);
+        assert!(buckets.buckets.contains_key(&(0, "0".to_owned())));
+        assert!(!buckets.buckets.contains_key(&(0, "1".to_owned())));
+        assert!(buckets.buckets.contains_key(&(0, "new".to_owned())));
+    }
+
+    #[test]
+    fn posted_busid_reads_a_single_fix() {
+        assert_eq!(
+            posted_busid(br#" {"latitude": 31.1, "busid" : "hp-01", "longitude": 77.2}"#),
+            Some("hp-01".to_owned())
+        );
+        assert_eq!(posted_busid(br#"[{"busid": "hp-01"}]"#), None);
+        assert_eq!(posted_busid(br#"{"latitude": 31.1}"#), None);
+        assert_eq!(posted_busid(br#"{"busid": "hp-0"#), None);
+    }
+}
@@ -0,0 +1,198 @@
+//! An in-memory copy of a table keyed by id, for rows that change far more
+//! often than they need to reach the database. Writes mark a row dirty; a
+//! periodic flush takes the dirty rows and writes them out in one go.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, RwLock};
+
+use rocket::serde::Deserialize;
+
+/// How soon a write to the store reaches the database.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(crate = "rocket::serde", rename_all = "lowercase")]
+pub enum Durability {
+    /// Written with every update, as well as kept in memory.
+    Immediate,
+    /// Written by the next flush; a crash loses at most one flush interval.
+    Batched,
+}
+
+/// The `[live_positions]` section of `Rocket.toml`.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct LiveConfig {
+    pub durability: Durability,
+    /// Seconds between flushes.
+    pub flush_interval: u64,
+}
+
+impl Default for LiveConfig {
+    fn default() -> Self {
+        LiveConfig {
+            durability: Durability::Batched,
+            flush_interval: 2,
+        }
+    }
+}
+
+/// `rows` is always locked before `dirty`, and held while `dirty` changes,
+/// so a flush never sees a row marked dirty before it is stored.
+#[derive(Debug)]
+struct Inner<T> {
+    rows: RwLock<HashMap<String, T>>,
+    dirty: Mutex<HashSet<String>>,
+}
+
+/// Rows by id. Cloning shares the same store.
+#[derive(Debug)]
+pub struct LiveStore<T> {
+    inner: Arc<Inner<T>>,
+    pub config: LiveConfig,
+}
+
+impl<T> Clone for LiveStore<T> {
+    fn clone(&self) -> Self {
+        LiveStore {
+            inner: self.inner.clone(),
+            config: self.config,
+        }
+    }
+}
+
+impl<T: Clone> LiveStore<T> {
+    pub fn new(config: LiveConfig, rows: impl IntoIterator<Item = (String, T)>) -> Self {
+        LiveStore {
+            inner: Arc::new(Inner {
+                rows: RwLock::new(rows.into_iter().collect()),
+                dirty: Mutex::new(HashSet::new()),
+            }),
+            config,
+        }
+    }
+
+    pub fn get(&self, id: &str) -> Option<T> {
+        self.inner.rows.read().unwrap().get(id).cloned()
+    }
+
+    pub fn ids(&self) -> Vec<String> {
+        self.inner.rows.read().unwrap().keys().cloned().collect()
+    }
+
+    pub fn all(&self) -> Vec<T> {
+        self.inner.rows.read().unwrap().values().cloned().collect()
+    }
+
+    /// Stores a row and marks it for the next flush.
+    pub fn put(&self, id: String, row: T) {
+        let mut rows = self.inner.rows.write().unwrap();
+        self.inner.dirty.lock().unwrap().insert(id.clone());
+        rows.insert(id, row);
+    }
+
+    /// Stores a row that was just read from or written to the database.
+    pub fn load(&self, id: String, row: T) {
+        self.inner.rows.write().unwrap().insert(id, row);
+    }
+
+    /// Drops a row without writing anything, for rows deleted or rewritten in
+    /// the database directly. The next read falls back to the database.
+    /// Returns whether the row was there.
+    pub fn forget(&self, id: &str) -> bool {
+        let mut rows = self.inner.rows.write().unwrap();
+        self.inner.dirty.lock().unwrap().remove(id);
+        rows.remove(id).is_some()
+    }
+
+    /// Takes the rows changed since the last flush. Hand them back with
+    /// [`LiveStore::restore`] if writing them fails.
+    pub fn take_dirty(&self) -> Vec<T> {
+        let rows = self.inner.rows.read().unwrap();
+        let dirty = std::mem::take(&mut *self.inner.dirty.lock().unwrap());
+        dirty
+            .iter()
+            .filter_map(|id| rows.get(id).cloned())
+            .collect()
+    }
+
+    /// Marks rows dirty again after a failed flush.
+    pub fn restore(&self, ids: impl IntoIterator<Item = String>) {
+        let rows = self.inner.rows.read().unwrap();
+        let mut dirty = self.inner.dirty.lock().unwrap();
+        dirty.extend(ids.into_iter().filter(|id| rows.contains_key(id)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store() -> LiveStore<(String, u32)> {
+        LiveStore::new(
+            LiveConfig::default(),
+            [("a".to_owned(), ("a".to_owned(), 0))],
+        )
+    }
+
+    #[test]
+    fn put_is_flushed_once() {
+        let store = store();
+        store.put("a".to_owned(), ("a".to_owned(), 1));
+        assert_eq!(store.get("a"), Some(("a".to_owned(), 1)));
+        assert_eq!(store.take_dirty(), vec![("a".to_owned(), 1)]);
+        assert!(store.take_dirty().is_empty());
+    }
+
+    #[test]
+    fn load_is_not_flushed() {
+        let store = store();
+        store.load("b".to_owned(), ("b".to_owned(), 1));
+        assert_eq!(store.get("b"), Some(("b".to_owned(), 1)));
+        assert!(store.take_dirty().is_empty());
+    }
+
+    #[test]
+    fn restore_flushes_again_with_the_newest_row() {
+        let store = store();
+        store.put("a".to_owned(), ("a".to_owned(), 1));
+        let rows = store.take_dirty();
+        store.put("a".to_owned(), ("a".to_owned(), 2));
+        store.restore(rows.into_iter().map(|(id, _)| id));
+        assert_eq!(store.take_dirty(), vec![("a".to_owned(), 2)]);
+    }
+
+    #[test]
+    fn forget_drops_the_row_and_its_flush() {
+        let store = store();
+        store.put("a".to_owned(), ("a".to_owned(), 1));
+        assert!(store.forget("a"));
+        assert!(!store.forget("a"));
+        assert_eq!(store.get("a"), None);
+        assert!(store.take_dirty().is_empty());
+        store.restore(["a".to_owned()]);
+        assert!(store.take_dirty().is_empty());
+    }
+
+    #[test]
+    fn last_put_is_never_lost_to_a_concurrent_flush() {
+        let store = store();
+        let writer = {
+            let store = store.clone();
+            std::thread::spawn(move || {
+                for n in 1..=10_000 {
+                    store.put("a".to_owned(), ("a".to_owned(), n));
+                }
+            })
+        };
+        let mut flushed = 0;
+        while !writer.is_finished() {
+            if let Some((_, n)) = store.take_dirty().pop() {
+                flushed = n;
+            }
+        }
+        writer.join().unwrap();
+        if let Some((_, n)) = store.take_dirty().pop() {
+            flushed = n;
+        }
+        assert_eq!(flushed, 10_000);
+    }
+}
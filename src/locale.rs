@@ -0,0 +1,122 @@
+//! `Accept-Language` negotiation for place names. A place id stays the same in
+//! every language; `place_names` holds what to show for it per language code,
+//! and a place without a name in any requested language shows its own name.
+
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+
+use rocket::request::{FromRequest, Outcome, Request};
+
+use self::diesel::prelude::*;
+use rocket_sync_db_pools::diesel;
+
+use crate::db;
+
+table! {
+    place_location (busid) {
+        busid -> Text,
+        name -> Text,
+    }
+}
+
+table! {
+    place_names (busid, lang) {
+        busid -> Text,
+        lang -> Text,
+        name -> Text,
+    }
+}
+
+/// The languages a client accepts, most preferred first, as lowercased
+/// language tags.
+#[derive(Debug, Clone, Default)]
+pub struct Languages(Vec<String>);
+
+impl Languages {
+    /// Parses an `Accept-Language` value such as `hi-IN,hi;q=0.9,en;q=0.8`.
+    /// Wildcards and ranges with `q=0` are dropped.
+    pub fn parse(header: &str) -> Languages {
+        let mut ranges: Vec<(f32, String)> = header
+            .split(',')
+            .filter_map(|range| {
+                let mut parts = range.split(';').map(str::trim);
+                let tag = parts.next()?.to_lowercase();
+                let quality = parts
+                    .find_map(|param| param.strip_prefix("q="))
+                    .map_or(Some(1.0), |q| q.parse().ok())?;
+                (!tag.is_empty() && tag != "*" && quality > 0.0).then_some((quality, tag))
+            })
+            .collect();
+        ranges.sort_by(|a, b| b.0.total_cmp(&a.0));
+        Languages(ranges.into_iter().map(|(_, tag)| tag).collect())
+    }
+
+    /// Picks the name in the most preferred language, matching `hi-IN`
+    /// against `hi` and the other way round when there is no exact match.
+    pub fn pick<'a>(&self, names: &'a BTreeMap<String, String>) -> Option<&'a str> {
+        let primary = |tag: &str| tag.split('-').next().unwrap_or_default().to_owned();
+        self.0.iter().find_map(|tag| {
+            names
+                .get(tag)
+                .or_else(|| {
+                    names
+                        .iter()
+                        .find(|(lang, _)| primary(lang) == primary(tag))
+                        .map(|(_, name)| name)
+                })
+                .map(String::as_str)
+        })
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Languages {
+    type Error = Infallible;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Infallible> {
+        Outcome::Success(
+            req.headers()
+                .get_one("Accept-Language")
+                .map(Languages::parse)
+                .unwrap_or_default(),
+        )
+    }
+}
+
+/// Names of `ids` in the client's language, keyed by place id. Every id gets
+/// an entry: the place's own name when there is no suitable translation, and
+/// the id itself for places that do not exist.
+pub fn localize(
+    conn: &mut db::Connection,
+    languages: &Languages,
+    ids: &[String],
+) -> QueryResult<BTreeMap<String, String>> {
+    let own: BTreeMap<String, String> = place_location::table
+        .filter(place_location::busid.eq_any(ids))
+        .load::<(String, String)>(conn)?
+        .into_iter()
+        .collect();
+    let mut names: BTreeMap<String, BTreeMap<String, String>> = BTreeMap::new();
+    if !languages.0.is_empty() {
+        let rows: Vec<(String, String, String)> = place_names::table
+            .filter(place_names::busid.eq_any(ids))
+            .load(conn)?;
+        for (busid, lang, name) in rows {
+            names
+                .entry(busid)
+                .or_default()
+                .insert(lang.to_lowercase(), name);
+        }
+    }
+    Ok(ids
+        .iter()
+        .map(|id| {
+            let name = names
+                .get(id)
+                .and_then(|names| languages.pick(names))
+                .or_else(|| own.get(id).map(String::as_str))
+                .unwrap_or(id);
+            (id.clone(), name.to_owned())
+        })
+        .collect())
+}
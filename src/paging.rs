@@ -0,0 +1,136 @@
+//! Limit/offset paging, sorting and bounding-box filters shared by the list
+//! endpoints. Without `?limit=` a list is returned whole, as before.
+
+use rocket::form::{self, FromFormField, ValueField};
+use rocket::request::Request;
+use rocket::response::{self, Responder};
+use rocket::serde::{json::Json, Serialize};
+
+/// Upper bound on `?limit=`.
+pub const MAX_LIMIT: i64 = 1000;
+
+#[derive(Debug, Clone, Copy, FromForm)]
+pub struct Paging {
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+impl Paging {
+    pub fn offset(&self) -> i64 {
+        self.offset.unwrap_or(0).max(0)
+    }
+
+    fn limit(&self) -> Option<i64> {
+        self.limit.map(|limit| limit.clamp(1, MAX_LIMIT))
+    }
+
+    /// Rows to fetch: one more than the page size, to tell whether another
+    /// page follows.
+    pub fn fetch(&self) -> i64 {
+        self.limit().map_or(i64::MAX, |limit| limit + 1)
+    }
+
+    /// Trims the fetched rows to a page and works out where the next starts.
+    pub fn page<T>(&self, mut items: Vec<T>) -> Page<T> {
+        let next = match self.limit() {
+            Some(limit) if items.len() as i64 > limit => {
+                items.truncate(limit as usize);
+                Some(self.offset() + limit)
+            }
+            _ => None,
+        };
+        Page { items, next }
+    }
+}
+
+/// One page of a list, served as a JSON array. When more rows follow, a
+/// `Link: <...>; rel="next"` header points at them.
+#[derive(Debug)]
+pub struct Page<T> {
+    items: Vec<T>,
+    next: Option<i64>,
+}
+
+impl<T> Page<T> {
+    /// The rows of this page and the offset of the next one.
+    pub fn into_parts(self) -> (Vec<T>, Option<i64>) {
+        (self.items, self.next)
+    }
+}
+
+/// The `Link` header value pointing from a request for `path?query` to the
+/// page starting at `next`.
+pub fn next_link(path: &str, query: Option<&str>, next: i64) -> String {
+    let mut query: Vec<&str> = query
+        .map(|query| query.split('&').collect())
+        .unwrap_or_default();
+    query.retain(|pair| !pair.is_empty() && !pair.starts_with("offset="));
+    let offset = format!("offset={next}");
+    query.push(&offset);
+    format!("<{}?{}>; rel=\"next\"", path, query.join("&"))
+}
+
+impl<'r, T: Serialize> Responder<'r, 'static> for Page<T> {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        let mut response = Json(self.items).respond_to(req)?;
+        if let Some(next) = self.next {
+            let query = req.uri().query().map(|query| query.as_str());
+            let link = next_link(req.uri().path().as_str(), query, next);
+            response.set_raw_header("Link", link);
+        }
+        Ok(response)
+    }
+}
+
+/// `?sort=field` sorts ascending, `?sort=-field` descending.
+#[derive(Debug, Clone)]
+pub struct Sort {
+    pub field: String,
+    pub descending: bool,
+}
+
+impl<'v> FromFormField<'v> for Sort {
+    fn from_value(field: ValueField<'v>) -> form::Result<'v, Self> {
+        Ok(match field.value.strip_prefix('-') {
+            Some(name) => Sort {
+                field: name.to_owned(),
+                descending: true,
+            },
+            None => Sort {
+                field: field.value.to_owned(),
+                descending: false,
+            },
+        })
+    }
+}
+
+/// `?bbox=minLon,minLat,maxLon,maxLat`, in degrees.
+#[derive(Debug, Clone, Copy)]
+pub struct Bbox {
+    pub min_lon: f32,
+    pub min_lat: f32,
+    pub max_lon: f32,
+    pub max_lat: f32,
+}
+
+impl<'v> FromFormField<'v> for Bbox {
+    fn from_value(field: ValueField<'v>) -> form::Result<'v, Self> {
+        let corners: Vec<f32> = field
+            .value
+            .split(',')
+            .map(|corner| corner.trim().parse())
+            .collect::<Result<_, _>>()
+            .map_err(|_| form::Error::validation("bbox corners must be numbers"))?;
+        match corners[..] {
+            [min_lon, min_lat, max_lon, max_lat] if min_lon <= max_lon && min_lat <= max_lat => {
+                Ok(Bbox {
+                    min_lon,
+                    min_lat,
+                    max_lon,
+                    max_lat,
+                })
+            }
+            _ => Err(form::Error::validation("expected minLon,minLat,maxLon,maxLat").into()),
+        }
+    }
+}
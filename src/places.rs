@@ -1,25 +1,46 @@
 use rocket::fairing::AdHoc;
-use rocket::response::{status::Created, Debug, Responder};
+use rocket::http::Status;
+use rocket::response::{
+    status::{BadRequest, Conflict, Created, Custom},
+    Debug, Responder,
+};
 use rocket::serde::{json::Json, Deserialize, Serialize};
-use rocket::{http, Build, Rocket};
+use rocket::{http, Build, Orbit, Rocket, State};
+
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use self::diesel::prelude::*;
+use self::diesel::sql_types::Text;
 use rocket_sync_db_pools::diesel;
 
 use rocket_cors::{AllowedHeaders, AllowedOrigins, CorsOptions, Method};
 
-#[database("diesel")]
-struct Db(diesel::SqliteConnection);
+use crate::admin::IncludeDeleted;
+use crate::audit::{Actor, Change};
+use crate::cache::{Conditional, ResponseCache};
+use crate::config;
+use crate::db::{self, Db};
+use crate::fuzzy::{self, Rank};
+use crate::locale::{self, place_names, Languages};
+use crate::paging::{Bbox, Paging, Sort};
+use crate::retention::RetentionConfig;
+use crate::revisions::LiveEdit;
 
 type Result<T, E = Debug<diesel::result::Error>> = std::result::Result<T, E>;
 
-#[derive(Debug, Clone, Deserialize, Serialize, Queryable, Insertable)]
+#[derive(Debug, Clone, Deserialize, Serialize, Queryable, Insertable, AsChangeset)]
 #[serde(crate = "rocket::serde")]
-#[diesel(table_name = place_location)]
+#[diesel(table_name = place_location, treat_none_as_null = true)]
 struct PlaceLocation {
+    /// Generated when the place is created and never changed.
     busid: String,
     latitude: f32,
     longitude: f32,
+    name: String,
+    /// When the place was deleted; it can be restored until it is purged.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    deleted_at: Option<i64>,
 }
 
 table! {
@@ -27,9 +48,209 @@ table! {
         busid -> Text,
         latitude -> Float,
         longitude -> Float,
+        name -> Text,
+        deleted_at -> Nullable<BigInt>,
+    }
+}
+
+/// The body of `POST /place/` and `PUT /place/one/<id>`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+struct PlaceIn {
+    name: String,
+    latitude: f32,
+    longitude: f32,
+}
+
+impl PlaceIn {
+    fn with_id(self, busid: String) -> PlaceLocation {
+        PlaceLocation {
+            busid,
+            latitude: self.latitude,
+            longitude: self.longitude,
+            name: self.name,
+            deleted_at: None,
+        }
+    }
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() as i64)
+}
+
+/// Picks an unused id for a new place: `p` and eight random hex digits. Ids
+/// of deleted places stay taken until they are purged.
+fn new_id(conn: &mut db::Connection) -> QueryResult<String> {
+    loop {
+        let id = format!("p{:08x}", rand::random::<u32>());
+        let taken = place_location::table
+            .filter(place_location::busid.eq(&id))
+            .count()
+            .get_result::<i64>(conn)?
+            > 0;
+        if !taken {
+            return Ok(id);
+        }
+    }
+}
+
+/// Replaces the name of each place with its name in the client's language.
+fn localized(
+    conn: &mut db::Connection,
+    languages: &Languages,
+    places: Vec<PlaceLocation>,
+) -> QueryResult<Vec<PlaceLocation>> {
+    let ids: Vec<String> = places.iter().map(|place| place.busid.clone()).collect();
+    let mut names = locale::localize(conn, languages, &ids)?;
+    Ok(places
+        .into_iter()
+        .map(|place| PlaceLocation {
+            name: names.remove(&place.busid).unwrap_or(place.name),
+            ..place
+        })
+        .collect())
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+struct NameIn {
+    name: String,
+}
+
+/// An alternate spelling or name a place can be searched by.
+#[derive(Debug, Clone, Deserialize, Serialize, Queryable, Insertable)]
+#[serde(crate = "rocket::serde")]
+#[diesel(table_name = place_aliases)]
+struct PlaceAlias {
+    alias: String,
+    busid: String,
+}
+
+table! {
+    place_aliases (alias) {
+        alias -> Text,
+        busid -> Text,
     }
 }
 
+diesel::sql_function!(fn lower(x: Text) -> Text);
+
+/// A search hit: the place and the name or alias that matched.
+#[derive(Debug, Clone, Serialize)]
+#[serde(crate = "rocket::serde")]
+struct Suggestion {
+    busid: String,
+    name: String,
+    matched: String,
+    latitude: f32,
+    longitude: f32,
+    #[serde(skip)]
+    rank: Rank,
+}
+
+/// Suggestions returned by `/place/search` unless `?limit=` says otherwise.
+const SEARCH_LIMIT: usize = 10;
+
+#[derive(Debug, Clone, Deserialize, Serialize, Queryable, Insertable, AsChangeset)]
+#[serde(crate = "rocket::serde")]
+#[diesel(table_name = routes, treat_none_as_null = true)]
+struct Routes {
+    routeid: String,
+    placeid: String,
+    inbound: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    deleted_at: Option<i64>,
+}
+
+impl Routes {
+    fn serves(&self, place: &str) -> bool {
+        let mut stops = self
+            .placeid
+            .split('|')
+            .chain(self.inbound.iter().flat_map(|i| i.split('|')));
+        stops.any(|stop| stop == place)
+    }
+
+    /// Maps every stop in both stop lists through `f`, dropping stops it maps
+    /// to `None`.
+    fn rewrite(self, f: impl Fn(&str) -> Option<String>) -> Routes {
+        let rewrite = |stops: &str| {
+            stops
+                .split('|')
+                .filter_map(&f)
+                .collect::<Vec<_>>()
+                .join("|")
+        };
+        Routes {
+            placeid: rewrite(&self.placeid),
+            inbound: self.inbound.as_deref().map(rewrite),
+            routeid: self.routeid,
+            deleted_at: self.deleted_at,
+        }
+    }
+
+    /// Drops `place` from both stop lists, or `None` when no outbound stop
+    /// would be left. An emptied inbound list falls back to the reverse of
+    /// the outbound one.
+    fn without(self, place: &str) -> Option<Routes> {
+        let route = self.rewrite(|stop| (stop != place).then(|| stop.to_owned()));
+        if route.placeid.is_empty() {
+            return None;
+        }
+        Some(Routes {
+            inbound: route.inbound.filter(|inbound| !inbound.is_empty()),
+            ..route
+        })
+    }
+}
+
+table! {
+    routes (routeid) {
+        routeid -> Text,
+        placeid -> Text,
+        inbound -> Nullable<Text>,
+        deleted_at -> Nullable<BigInt>,
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, Queryable, Insertable)]
+#[serde(crate = "rocket::serde")]
+#[diesel(table_name = busses)]
+struct Busses {
+    placeid: String,
+    busid: String,
+}
+
+table! {
+    busses (placeid) {
+        placeid -> Text,
+        busid -> Text,
+    }
+}
+
+/// A partial update of a place.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+struct PlacePatch {
+    name: Option<String>,
+    latitude: Option<f32>,
+    longitude: Option<f32>,
+}
+
+/// Fields `/place/all` can be sorted by.
+const SORTS: &[&str] = &["busid", "name", "latitude", "longitude"];
+
+/// What deleting a place does to the routes that stop there.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromFormField)]
+enum OnDelete {
+    /// Refuse the delete and list the routes in the way.
+    Restrict,
+    /// Remove the place from the stop lists of those routes.
+    Cascade,
+}
+
 fn core_options() -> CorsOptions {
     rocket_cors::CorsOptions {
         allowed_origins: AllowedOrigins::all(),
@@ -38,6 +259,8 @@ fn core_options() -> CorsOptions {
             http::Method::Post,
             http::Method::Options,
             http::Method::Delete,
+            http::Method::Put,
+            http::Method::Patch,
         ]
         .into_iter()
         .map(Method)
@@ -50,16 +273,74 @@ fn core_options() -> CorsOptions {
     }
 }
 
+/// Creates a place under a generated id.
 #[post("/", data = "<post>")]
-async fn bus_post<'r, 'o: 'r>(db: Db, post: Json<PlaceLocation>) -> Result<impl Responder<'r, 'o>> {
-    let post_value = post.clone();
-    db.run(move |conn| {
-        diesel::insert_into(place_location::table)
-            .values(&*post_value)
-            .execute(conn)
-    })
-    .await?;
-    let out = Created::new("/").body(post);
+async fn bus_post<'r, 'o: 'r>(
+    _live: LiveEdit,
+    db: Db,
+    actor: Actor,
+    post: Json<PlaceIn>,
+) -> Result<impl Responder<'r, 'o>> {
+    let post_value = post.into_inner();
+    let place = db
+        .write(move |conn| {
+            conn.transaction::<_, diesel::result::Error, _>(|conn| {
+                let place = post_value.with_id(new_id(conn)?);
+                diesel::insert_into(place_location::table)
+                    .values(&place)
+                    .execute(conn)?;
+                Change::new("create", "place", &place.busid)
+                    .after(&place)
+                    .record(conn, &actor)?;
+                Ok(place)
+            })
+        })
+        .await?;
+    let out = Created::new(format!("/place/one/{}", place.busid)).body(Json(place));
+    let options = match core_options().to_cors() {
+        Ok(a) => a,
+        Err(a) => return Ok(Err(a)),
+    };
+    Ok(options.respond_owned(move |guard| guard.responder(out)))
+}
+
+/// Creates or replaces a place, answering 201 or 200. The id stays as given
+/// in the path; renaming a place only changes its `name`. Replacing a deleted
+/// place brings it back as new.
+#[put("/one/<id>", data = "<post>")]
+async fn put_one_bus<'r, 'o: 'r>(
+    _live: LiveEdit,
+    db: Db,
+    actor: Actor,
+    id: String,
+    post: Json<PlaceIn>,
+) -> Result<impl Responder<'r, 'o>> {
+    let place = post.into_inner().with_id(id);
+    let out = db
+        .write(move |conn| {
+            conn.transaction::<_, diesel::result::Error, _>(|conn| {
+                let before: Option<PlaceLocation> = place_location::table
+                    .filter(place_location::busid.eq(&place.busid))
+                    .filter(place_location::deleted_at.is_null())
+                    .first(conn)
+                    .optional()?;
+                diesel::insert_into(place_location::table)
+                    .values(&place)
+                    .on_conflict(place_location::busid)
+                    .do_update()
+                    .set(&place)
+                    .execute(conn)?;
+                let exists = before.is_some();
+                let action = if exists { "update" } else { "create" };
+                Change::new(action, "place", &place.busid)
+                    .before(&before)
+                    .after(&place)
+                    .record(conn, &actor)?;
+                let status = if exists { Status::Ok } else { Status::Created };
+                Ok(Custom(status, Json(place)))
+            })
+        })
+        .await?;
     let options = match core_options().to_cors() {
         Ok(a) => a,
         Err(a) => return Ok(Err(a)),
@@ -67,13 +348,68 @@ async fn bus_post<'r, 'o: 'r>(db: Db, post: Json<PlaceLocation>) -> Result<impl
     Ok(options.respond_owned(move |guard| guard.responder(out)))
 }
 
+/// Updates the given fields of a place.
+#[patch("/one/<id>", data = "<post>")]
+async fn patch_one_bus<'r, 'o: 'r>(
+    _live: LiveEdit,
+    db: Db,
+    actor: Actor,
+    id: String,
+    post: Json<PlacePatch>,
+) -> Result<impl Responder<'r, 'o>> {
+    let patch = post.into_inner();
+    let out = db
+        .write(move |conn| {
+            conn.transaction::<_, diesel::result::Error, _>(|conn| {
+                let place: Option<PlaceLocation> = place_location::table
+                    .filter(place_location::busid.eq(&id))
+                    .filter(place_location::deleted_at.is_null())
+                    .first(conn)
+                    .optional()?;
+                let mut place = match place {
+                    Some(place) => place,
+                    None => return Ok(None),
+                };
+                let before = place.clone();
+                place.name = patch.name.unwrap_or(place.name);
+                place.latitude = patch.latitude.unwrap_or(place.latitude);
+                place.longitude = patch.longitude.unwrap_or(place.longitude);
+                diesel::insert_into(place_location::table)
+                    .values(&place)
+                    .on_conflict(place_location::busid)
+                    .do_update()
+                    .set(&place)
+                    .execute(conn)?;
+                Change::new("update", "place", &place.busid)
+                    .before(&before)
+                    .after(&place)
+                    .record(conn, &actor)?;
+                Ok(Some(Json(place)))
+            })
+        })
+        .await?;
+    let options = match core_options().to_cors() {
+        Ok(a) => a,
+        Err(a) => return Ok(Err(a)),
+    };
+    Ok(options.respond_owned(move |guard| guard.responder(out)))
+}
+
+/// Place ids; deleted places too with `?include_deleted=true` from the admin.
 #[get("/")]
-async fn list<'r, 'o: 'r>(db: Db) -> Result<impl Responder<'r, 'o>> {
+async fn list<'r, 'o: 'r>(
+    db: Db,
+    include_deleted: IncludeDeleted,
+) -> Result<impl Responder<'r, 'o>> {
     let ids: Vec<String> = db
         .run(move |conn| {
-            place_location::table
+            let mut query = place_location::table
                 .select(place_location::busid)
-                .load(conn)
+                .into_boxed();
+            if !include_deleted.0 {
+                query = query.filter(place_location::deleted_at.is_null());
+            }
+            query.load(conn)
         })
         .await?;
 
@@ -85,16 +421,171 @@ async fn list<'r, 'o: 'r>(db: Db) -> Result<impl Responder<'r, 'o>> {
     Ok(options.respond_owned(move |guard| guard.responder(out)))
 }
 
-#[get("/all")]
-async fn list_all<'r, 'o: 'r>(db: Db) -> Result<impl Responder<'r, 'o>> {
-    let ids: Vec<PlaceLocation> = db
+/// Places, optionally filtered by `?bbox=`, sorted by `?sort=` and paged with
+/// `?limit=` and `?offset=`. `?include_deleted=true` from the admin lists
+/// deleted places too, with their `deleted_at`.
+#[get("/all?<bbox>&<sort>&<paging..>")]
+async fn list_all<'r, 'o: 'r>(
+    db: Db,
+    cache: &State<ResponseCache>,
+    conditional: Conditional,
+    languages: Languages,
+    bbox: Option<Bbox>,
+    sort: Option<Sort>,
+    include_deleted: IncludeDeleted,
+    paging: Paging,
+) -> Result<impl Responder<'r, 'o>> {
+    let sort = sort.unwrap_or(Sort {
+        field: "busid".to_owned(),
+        descending: false,
+    });
+    let out = if SORTS.contains(&&*sort.field) {
+        let entry = match cache.get(&conditional) {
+            Some(entry) => entry,
+            None => {
+                let version = cache.version();
+                let ids: Vec<PlaceLocation> = db
+                    .run(move |conn| {
+                        let mut query = place_location::table.into_boxed();
+                        if !include_deleted.0 {
+                            query = query.filter(place_location::deleted_at.is_null());
+                        }
+                        if let Some(bbox) = bbox {
+                            query = query
+                                .filter(
+                                    place_location::latitude.between(bbox.min_lat, bbox.max_lat),
+                                )
+                                .filter(
+                                    place_location::longitude.between(bbox.min_lon, bbox.max_lon),
+                                );
+                        }
+                        query = match (sort.field.as_str(), sort.descending) {
+                            ("name", false) => query.order(place_location::name.asc()),
+                            ("name", true) => query.order(place_location::name.desc()),
+                            ("latitude", false) => query.order(place_location::latitude.asc()),
+                            ("latitude", true) => query.order(place_location::latitude.desc()),
+                            ("longitude", false) => query.order(place_location::longitude.asc()),
+                            ("longitude", true) => query.order(place_location::longitude.desc()),
+                            (_, false) => query.order(place_location::busid.asc()),
+                            (_, true) => query.order(place_location::busid.desc()),
+                        };
+                        let places = query
+                            .then_order_by(place_location::busid.asc())
+                            .limit(paging.fetch())
+                            .offset(paging.offset())
+                            .load::<PlaceLocation>(conn)?;
+                        localized(conn, &languages, places)
+                    })
+                    .await?;
+                let (places, next) = paging.page(ids).into_parts();
+                cache.put(&conditional, version, &places, next)
+            }
+        };
+        Ok(conditional.respond(entry))
+    } else {
+        Err(BadRequest(Some("unknown sort field")))
+    };
+    let options = match core_options().to_cors() {
+        Ok(a) => a,
+        Err(a) => return Ok(Err(a)),
+    };
+    Ok(options.respond_owned(move |guard| guard.responder(out)))
+}
+
+/// Places whose name or an alias matches `?q=`, best first: exact, prefix,
+/// word prefix and substring matches, then near misses by edit distance.
+#[get("/search?<q>&<limit>")]
+async fn search<'r, 'o: 'r>(
+    db: Db,
+    q: String,
+    limit: Option<usize>,
+    languages: Languages,
+) -> Result<impl Responder<'r, 'o>> {
+    let query = fuzzy::normalize(&q);
+    let limit = limit.unwrap_or(SEARCH_LIMIT).clamp(1, 100);
+    let (places, aliases, localized) = db
+        .run(move |conn| -> QueryResult<_> {
+            let places = place_location::table
+                .filter(place_location::deleted_at.is_null())
+                .load::<PlaceLocation>(conn)?;
+            let aliases = place_aliases::table.load::<PlaceAlias>(conn)?;
+            let localized = place_names::table.load::<(String, String, String)>(conn)?;
+            Ok((places, aliases, localized))
+        })
+        .await?;
+
+    let mut best: BTreeMap<String, Suggestion> = BTreeMap::new();
+    let names = places
+        .iter()
+        .map(|place| (place.name.as_str(), place.busid.as_str()))
+        .chain(
+            aliases
+                .iter()
+                .map(|alias| (alias.alias.as_str(), alias.busid.as_str())),
+        )
+        .chain(
+            localized
+                .iter()
+                .map(|(busid, _, name)| (name.as_str(), busid.as_str())),
+        );
+    let located: BTreeMap<&str, &PlaceLocation> = places
+        .iter()
+        .map(|place| (place.busid.as_str(), place))
+        .collect();
+    for (name, busid) in names {
+        let (Some(rank), Some(place)) = (fuzzy::rank(&query, name), located.get(busid)) else {
+            continue;
+        };
+        if best.get(busid).is_some_and(|hit| hit.rank <= rank) {
+            continue;
+        }
+        best.insert(
+            busid.to_owned(),
+            Suggestion {
+                busid: busid.to_owned(),
+                name: place.name.clone(),
+                matched: name.to_owned(),
+                latitude: place.latitude,
+                longitude: place.longitude,
+                rank,
+            },
+        );
+    }
+    let mut hits: Vec<Suggestion> = best.into_values().collect();
+    hits.sort_by(|a, b| a.rank.cmp(&b.rank).then_with(|| a.busid.cmp(&b.busid)));
+    hits.truncate(limit);
+    let ids: Vec<String> = hits.iter().map(|hit| hit.busid.clone()).collect();
+    let mut names = db
+        .run(move |conn| locale::localize(conn, &languages, &ids))
+        .await?;
+    for hit in &mut hits {
+        if let Some(name) = names.remove(&hit.busid) {
+            hit.name = name;
+        }
+    }
+
+    let out = Json(hits);
+    let options = match core_options().to_cors() {
+        Ok(a) => a,
+        Err(a) => return Ok(Err(a)),
+    };
+    Ok(options.respond_owned(move |guard| guard.responder(out)))
+}
+
+/// Names of a place keyed by language code.
+#[get("/one/<id>/names")]
+async fn list_names<'r, 'o: 'r>(db: Db, id: String) -> Result<impl Responder<'r, 'o>> {
+    let names: Vec<(String, String)> = db
         .run(move |conn| {
-            place_location::table
-                .load::<PlaceLocation>(conn)
+            place_names::table
+                .select((place_names::lang, place_names::name))
+                .filter(place_names::busid.eq(id))
+                .order(place_names::lang)
+                .load(conn)
         })
         .await?;
 
-    let out = Json(ids);
+    let out = Json(names.into_iter().collect::<BTreeMap<_, _>>());
     let options = match core_options().to_cors() {
         Ok(a) => a,
         Err(a) => return Ok(Err(a)),
@@ -102,17 +593,122 @@ async fn list_all<'r, 'o: 'r>(db: Db) -> Result<impl Responder<'r, 'o>> {
     Ok(options.respond_owned(move |guard| guard.responder(out)))
 }
 
-#[get("/one/<id>")]
-async fn get_one_bus<'r, 'o: 'r>(db: Db, id: String) -> Result<impl Responder<'r, 'o>> {
-    let out: Json<PlaceLocation> = db
+/// Sets the name of a place in one language, answering 201 or 200, or 404
+/// when the place does not exist.
+#[put("/one/<id>/names/<lang>", data = "<post>")]
+async fn put_name<'r, 'o: 'r>(
+    db: Db,
+    actor: Actor,
+    id: String,
+    lang: String,
+    post: Json<NameIn>,
+) -> Result<impl Responder<'r, 'o>> {
+    let name = post.into_inner().name;
+    let lang = lang.to_lowercase();
+    let out = db
+        .write(move |conn| {
+            conn.transaction::<_, diesel::result::Error, _>(|conn| {
+                let exists = place_location::table
+                    .filter(place_location::busid.eq(&id))
+                    .filter(place_location::deleted_at.is_null())
+                    .count()
+                    .get_result::<i64>(conn)?
+                    > 0;
+                if !exists {
+                    return Ok(None);
+                }
+                let before: Option<String> = place_names::table
+                    .select(place_names::name)
+                    .filter(place_names::busid.eq(&id))
+                    .filter(place_names::lang.eq(&lang))
+                    .first(conn)
+                    .optional()?;
+                let status = if before.is_some() {
+                    diesel::update(place_names::table)
+                        .filter(place_names::busid.eq(&id))
+                        .filter(place_names::lang.eq(&lang))
+                        .set(place_names::name.eq(&name))
+                        .execute(conn)?;
+                    Status::Ok
+                } else {
+                    diesel::insert_into(place_names::table)
+                        .values((
+                            place_names::busid.eq(&id),
+                            place_names::lang.eq(&lang),
+                            place_names::name.eq(&name),
+                        ))
+                        .execute(conn)?;
+                    Status::Created
+                };
+                let action = if before.is_some() { "update" } else { "create" };
+                Change::new(action, "place_name", &format!("{id}/{lang}"))
+                    .before(&before)
+                    .after(&name)
+                    .record(conn, &actor)?;
+                Ok(Some(Custom(status, ())))
+            })
+        })
+        .await?;
+
+    let options = match core_options().to_cors() {
+        Ok(a) => a,
+        Err(a) => return Ok(Err(a)),
+    };
+    Ok(options.respond_owned(move |guard| guard.responder(out)))
+}
+
+#[delete("/one/<id>/names/<lang>")]
+async fn delete_name<'r, 'o: 'r>(
+    db: Db,
+    actor: Actor,
+    id: String,
+    lang: String,
+) -> Result<impl Responder<'r, 'o>> {
+    let lang = lang.to_lowercase();
+    let out = db
+        .write(move |conn| {
+            conn.transaction::<_, diesel::result::Error, _>(|conn| {
+                let before: Option<String> = place_names::table
+                    .select(place_names::name)
+                    .filter(place_names::busid.eq(&id))
+                    .filter(place_names::lang.eq(&lang))
+                    .first(conn)
+                    .optional()?;
+                if before.is_none() {
+                    return Ok(None);
+                }
+                diesel::delete(place_names::table)
+                    .filter(place_names::busid.eq(&id))
+                    .filter(place_names::lang.eq(&lang))
+                    .execute(conn)?;
+                Change::new("delete", "place_name", &format!("{id}/{lang}"))
+                    .before(&before)
+                    .record(conn, &actor)?;
+                Ok(Some(()))
+            })
+        })
+        .await?;
+
+    let options = match core_options().to_cors() {
+        Ok(a) => a,
+        Err(a) => return Ok(Err(a)),
+    };
+    Ok(options.respond_owned(move |guard| guard.responder(out)))
+}
+
+#[get("/one/<id>/aliases")]
+async fn list_aliases<'r, 'o: 'r>(db: Db, id: String) -> Result<impl Responder<'r, 'o>> {
+    let aliases: Vec<String> = db
         .run(move |conn| {
-            place_location::table
-                .filter(place_location::busid.eq(id))
-                .first(conn)
+            place_aliases::table
+                .select(place_aliases::alias)
+                .filter(place_aliases::busid.eq(id))
+                .order(place_aliases::alias)
+                .load(conn)
         })
-        .await
-        .map(Json)?;
+        .await?;
 
+    let out = Json(aliases);
     let options = match core_options().to_cors() {
         Ok(a) => a,
         Err(a) => return Ok(Err(a)),
@@ -120,17 +716,209 @@ async fn get_one_bus<'r, 'o: 'r>(db: Db, id: String) -> Result<impl Responder<'r
     Ok(options.respond_owned(move |guard| guard.responder(out)))
 }
 
-#[delete("/one/<id>")]
-async fn delete_one_bus<'r, 'o: 'r>(db: Db, id: String) -> Result<impl Responder<'r, 'o>> {
-    let out: usize = db
+/// Adds an alias to a place. 404 when the place does not exist, 409 when the
+/// alias already names another place.
+#[put("/one/<id>/aliases/<alias>")]
+async fn put_alias<'r, 'o: 'r>(
+    db: Db,
+    actor: Actor,
+    id: String,
+    alias: String,
+) -> Result<impl Responder<'r, 'o>> {
+    let out = db
+        .write(move |conn| {
+            conn.transaction::<_, diesel::result::Error, _>(|conn| {
+                let exists = place_location::table
+                    .filter(place_location::busid.eq(&id))
+                    .filter(place_location::deleted_at.is_null())
+                    .count()
+                    .get_result::<i64>(conn)?
+                    > 0;
+                if !exists {
+                    return Ok(Ok(None));
+                }
+                let owner: Option<String> = place_aliases::table
+                    .select(place_aliases::busid)
+                    .filter(lower(place_aliases::alias).eq(alias.to_lowercase()))
+                    .first(conn)
+                    .optional()?;
+                match owner {
+                    Some(owner) if owner != id => {
+                        Ok(Err(Conflict(Some("alias already names another place"))))
+                    }
+                    Some(_) => Ok(Ok(Some(Custom(Status::Ok, ())))),
+                    None => {
+                        let added = PlaceAlias { alias, busid: id };
+                        diesel::insert_into(place_aliases::table)
+                            .values(&added)
+                            .execute(conn)?;
+                        Change::new("create", "place_alias", &added.alias)
+                            .after(&added)
+                            .record(conn, &actor)?;
+                        Ok(Ok(Some(Custom(Status::Created, ()))))
+                    }
+                }
+            })
+        })
+        .await?;
+
+    let options = match core_options().to_cors() {
+        Ok(a) => a,
+        Err(a) => return Ok(Err(a)),
+    };
+    Ok(options.respond_owned(move |guard| guard.responder(out)))
+}
+
+#[delete("/one/<id>/aliases/<alias>")]
+async fn delete_alias<'r, 'o: 'r>(
+    db: Db,
+    actor: Actor,
+    id: String,
+    alias: String,
+) -> Result<impl Responder<'r, 'o>> {
+    let out = db
+        .write(move |conn| {
+            conn.transaction::<_, diesel::result::Error, _>(|conn| {
+                let before: Option<PlaceAlias> = place_aliases::table
+                    .filter(place_aliases::busid.eq(&id))
+                    .filter(lower(place_aliases::alias).eq(alias.to_lowercase()))
+                    .first(conn)
+                    .optional()?;
+                let before = match before {
+                    Some(before) => before,
+                    None => return Ok(None),
+                };
+                diesel::delete(place_aliases::table)
+                    .filter(place_aliases::alias.eq(&before.alias))
+                    .execute(conn)?;
+                Change::new("delete", "place_alias", &before.alias)
+                    .before(&before)
+                    .record(conn, &actor)?;
+                Ok(Some(()))
+            })
+        })
+        .await?;
+
+    let options = match core_options().to_cors() {
+        Ok(a) => a,
+        Err(a) => return Ok(Err(a)),
+    };
+    Ok(options.respond_owned(move |guard| guard.responder(out)))
+}
+
+/// A place, even a deleted one with `?include_deleted=true` from the admin;
+/// 404 when there is no such place.
+#[get("/one/<id>")]
+async fn get_one_bus<'r, 'o: 'r>(
+    db: Db,
+    id: String,
+    include_deleted: IncludeDeleted,
+    languages: Languages,
+) -> Result<impl Responder<'r, 'o>> {
+    let out: Option<Json<PlaceLocation>> = db
         .run(move |conn| {
-            diesel::delete(place_location::table)
+            let mut query = place_location::table
                 .filter(place_location::busid.eq(id))
-                .execute(conn)
+                .into_boxed();
+            if !include_deleted.0 {
+                query = query.filter(place_location::deleted_at.is_null());
+            }
+            let place: PlaceLocation = match query.first(conn).optional()? {
+                Some(place) => place,
+                None => return Ok(None),
+            };
+            let mut places = localized(conn, &languages, vec![place])?;
+            Ok::<_, diesel::result::Error>(Some(places.remove(0)))
+        })
+        .await?
+        .map(Json);
+
+    let options = match core_options().to_cors() {
+        Ok(a) => a,
+        Err(a) => return Ok(Err(a)),
+    };
+    Ok(options.respond_owned(move |guard| guard.responder(out)))
+}
+
+/// Deletes a place, keeping it restorable until the retention window in
+/// `[soft_delete]` runs out. Routes still stopping there, deleted ones
+/// included, make the delete fail with 409 and their ids, unless
+/// `?on_delete=cascade` takes the place off them too. A cascade still fails
+/// with the ids of the routes that stop nowhere else, which need deleting
+/// first.
+#[delete("/one/<id>?<on_delete>")]
+async fn delete_one_bus<'r, 'o: 'r>(
+    _live: LiveEdit,
+    db: Db,
+    actor: Actor,
+    id: String,
+    on_delete: Option<OnDelete>,
+) -> Result<impl Responder<'r, 'o>> {
+    let on_delete = on_delete.unwrap_or(OnDelete::Restrict);
+    let out = db
+        .write(move |conn| {
+            conn.transaction::<_, diesel::result::Error, _>(|conn| {
+                let before: Option<PlaceLocation> = place_location::table
+                    .filter(place_location::busid.eq(&id))
+                    .filter(place_location::deleted_at.is_null())
+                    .first(conn)
+                    .optional()?;
+                let before = match before {
+                    Some(before) => before,
+                    None => return Ok(Ok(None)),
+                };
+                let serving: Vec<Routes> = routes::table
+                    .load::<Routes>(conn)?
+                    .into_iter()
+                    .filter(|route| route.serves(&id))
+                    .collect();
+                if !serving.is_empty() && on_delete == OnDelete::Restrict {
+                    let ids: Vec<String> = serving.into_iter().map(|route| route.routeid).collect();
+                    return Ok(Err(Conflict(Some(Json(ids)))));
+                }
+                let mut updated = Vec::new();
+                let mut emptied = Vec::new();
+                for before in serving {
+                    match before.clone().without(&id) {
+                        Some(route) => updated.push((before, route)),
+                        None => emptied.push(before.routeid),
+                    }
+                }
+                if !emptied.is_empty() {
+                    return Ok(Err(Conflict(Some(Json(emptied)))));
+                }
+                for (before, route) in updated {
+                    diesel::insert_into(routes::table)
+                        .values(&route)
+                        .on_conflict(routes::routeid)
+                        .do_update()
+                        .set(&route)
+                        .execute(conn)?;
+                    Change::new("update", "route", &route.routeid)
+                        .before(&before)
+                        .after(&route)
+                        .record(conn, &actor)?;
+                }
+                let place = PlaceLocation {
+                    deleted_at: Some(now()),
+                    ..before.clone()
+                };
+                diesel::update(place_location::table)
+                    .filter(place_location::busid.eq(&id))
+                    .set(place_location::deleted_at.eq(place.deleted_at))
+                    .execute(conn)?;
+                diesel::delete(busses::table)
+                    .filter(busses::placeid.eq(&id))
+                    .execute(conn)?;
+                Change::new("delete", "place", &id)
+                    .before(&before)
+                    .after(&place)
+                    .record(conn, &actor)?;
+                Ok(Ok(Some(())))
+            })
         })
         .await?;
 
-    let out = (out == 1).then_some(());
     let options = match core_options().to_cors() {
         Ok(a) => a,
         Err(a) => return Ok(Err(a)),
@@ -138,16 +926,107 @@ async fn delete_one_bus<'r, 'o: 'r>(db: Db, id: String) -> Result<impl Responder
     Ok(options.respond_owned(move |guard| guard.responder(out)))
 }
 
-async fn run_migrations(rocket: Rocket<Build>) -> Rocket<Build> {
-    use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+/// Undoes the delete of a place, which comes back with its names and
+/// aliases. Routes a cascading delete took it off stay as they are.
+#[post("/one/<id>/restore")]
+async fn restore_one_bus<'r, 'o: 'r>(
+    _live: LiveEdit,
+    db: Db,
+    actor: Actor,
+    id: String,
+) -> Result<impl Responder<'r, 'o>> {
+    let out = db
+        .write(move |conn| {
+            conn.transaction::<_, diesel::result::Error, _>(|conn| {
+                let before: Option<PlaceLocation> = place_location::table
+                    .filter(place_location::busid.eq(&id))
+                    .filter(place_location::deleted_at.is_not_null())
+                    .first(conn)
+                    .optional()?;
+                let before = match before {
+                    Some(before) => before,
+                    None => return Ok(None),
+                };
+                let place = PlaceLocation {
+                    deleted_at: None,
+                    ..before.clone()
+                };
+                diesel::update(place_location::table)
+                    .filter(place_location::busid.eq(&id))
+                    .set(place_location::deleted_at.eq(place.deleted_at))
+                    .execute(conn)?;
+                Change::new("restore", "place", &id)
+                    .before(&before)
+                    .after(&place)
+                    .record(conn, &actor)?;
+                Ok(Some(Json(place)))
+            })
+        })
+        .await?;
 
-    const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
+    let options = match core_options().to_cors() {
+        Ok(a) => a,
+        Err(a) => return Ok(Err(a)),
+    };
+    Ok(options.respond_owned(move |guard| guard.responder(out)))
+}
+
+/// Removes places deleted at or before `cutoff` for good, along with their
+/// names and aliases, and returns how many went. A place still on the stop
+/// list of a deleted route waits for that route to be purged first.
+fn purge(conn: &mut db::Connection, cutoff: i64) -> QueryResult<usize> {
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        let expired: Vec<PlaceLocation> = place_location::table
+            .filter(place_location::deleted_at.le(cutoff))
+            .load(conn)?;
+        let routes: Vec<Routes> = routes::table.load(conn)?;
+        let mut purged = 0;
+        for place in expired {
+            if routes.iter().any(|route| route.serves(&place.busid)) {
+                continue;
+            }
+            diesel::delete(place_location::table)
+                .filter(place_location::busid.eq(&place.busid))
+                .execute(conn)?;
+            Change::new("purge", "place", &place.busid)
+                .before(&place)
+                .record(conn, &Actor::system())?;
+            purged += 1;
+        }
+        Ok(purged)
+    })
+}
+
+/// Purges expired deletes every `purge_interval` seconds.
+async fn start_purging(rocket: &Rocket<Orbit>, config: RetentionConfig) {
+    let cache = rocket
+        .state::<ResponseCache>()
+        .expect("response cache")
+        .clone();
+    let db = Db::get_one(rocket).await.expect("database connection");
+    rocket::tokio::spawn(async move {
+        let mut interval = rocket::tokio::time::interval(config.period());
+        loop {
+            interval.tick().await;
+            let cutoff = config.cutoff();
+            match db.write(move |conn| purge(conn, cutoff)).await {
+                Ok(0) => {}
+                // Listings with `?include_deleted=true` showed them.
+                Ok(_) => cache.bump(),
+                Err(e) => error!("purging deleted places: {}", e),
+            }
+        }
+    });
+}
+
+async fn run_migrations(rocket: Rocket<Build>) -> Rocket<Build> {
+    use diesel_migrations::MigrationHarness;
 
     Db::get_one(&rocket)
         .await
         .expect("database connection")
         .run(|conn| {
-            conn.run_pending_migrations(MIGRATIONS)
+            conn.run_pending_migrations(db::MIGRATIONS)
                 .expect("diesel migrations");
         })
         .await;
@@ -156,13 +1035,35 @@ async fn run_migrations(rocket: Rocket<Build>) -> Rocket<Build> {
 }
 
 pub fn place_data() -> AdHoc {
-    AdHoc::on_ignite("Data related to places", |rocket| async {
-        rocket
-            .attach(Db::fairing())
+    AdHoc::try_on_ignite("Data related to places", |rocket| async {
+        let config: RetentionConfig = match config::read(&rocket, "soft_delete") {
+            Some(config) => config,
+            None => return Err(rocket),
+        };
+        Ok(rocket
             .attach(AdHoc::on_ignite("Diesel Migrations", run_migrations))
+            .attach(AdHoc::on_liftoff("Purge deleted places", move |rocket| {
+                Box::pin(start_purging(rocket, config))
+            }))
             .mount(
                 "/place",
-                routes![bus_post, list,list_all, get_one_bus, delete_one_bus],
-            )
+                routes![
+                    bus_post,
+                    put_one_bus,
+                    patch_one_bus,
+                    list,
+                    list_all,
+                    search,
+                    get_one_bus,
+                    list_names,
+                    put_name,
+                    delete_name,
+                    list_aliases,
+                    put_alias,
+                    delete_alias,
+                    delete_one_bus,
+                    restore_one_bus
+                ],
+            ))
     })
 }
@@ -0,0 +1,40 @@
+//! How long deleted places and routes are kept. Deleting one only stamps its
+//! `deleted_at`, so it can still be restored; a periodic job in each module
+//! removes rows deleted longer ago than the retention window for good.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use rocket::serde::Deserialize;
+
+/// The `[soft_delete]` section of `Rocket.toml`.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct RetentionConfig {
+    /// Days a deleted row can still be restored.
+    pub retention_days: u64,
+    /// Seconds between purges.
+    pub purge_interval: u64,
+}
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        RetentionConfig {
+            retention_days: 30,
+            purge_interval: 3600,
+        }
+    }
+}
+
+impl RetentionConfig {
+    /// Rows deleted at or before this time (unix seconds) are purged.
+    pub fn cutoff(&self) -> i64 {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs() as i64);
+        now - (self.retention_days * 86_400) as i64
+    }
+
+    pub fn period(&self) -> Duration {
+        Duration::from_secs(self.purge_interval.max(1))
+    }
+}
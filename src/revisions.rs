@@ -0,0 +1,1061 @@
+//! Draft and published revisions of the network: the places and the routes.
+//! A draft starts as a copy of the live network and is edited here without
+//! riders seeing it; publishing copies it over the live tables in one
+//! transaction and lists the buses at each place from the routes they run,
+//! as `/routes` does. The revision it replaces is kept as `retired`, and
+//! rolling back publishes one of those again.
+//!
+//! The writes to places, routes and `busses` under `/place`, `/routes` and
+//! `/busses` take a [`LiveEdit`], which is refused with 403 unless
+//! `[revisions] live_edits` lets fixes that can't wait skip the draft. A
+//! publish or rollback that would overwrite such edits is refused with what
+//! changed live since, unless `?force=true`.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rocket::fairing::AdHoc;
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::response::{
+    status::{Conflict, Created, Custom},
+    Debug, Responder,
+};
+use rocket::serde::json::{self, Json};
+use rocket::serde::{Deserialize, Serialize};
+use rocket::{http, Build, Rocket};
+
+use self::diesel::prelude::*;
+use rocket_cors::{AllowedHeaders, AllowedOrigins, CorsOptions, Method};
+use rocket_sync_db_pools::diesel;
+
+use crate::admin::Admin;
+use crate::audit::{Actor, Change};
+use crate::config;
+use crate::db::{self, Db};
+use crate::paging::Paging;
+use crate::routes::repair_busses;
+
+type Result<T, E = Debug<diesel::result::Error>> = std::result::Result<T, E>;
+
+/// Being edited; never seen by riders.
+const DRAFT: &str = "draft";
+/// The revision the live network was last published from.
+const PUBLISHED: &str = "published";
+/// Published before and replaced since; can be rolled back to.
+const RETIRED: &str = "retired";
+
+/// The `[revisions]` section of `Rocket.toml`.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct RevisionConfig {
+    /// Whether `/place`, `/routes` and `/busses` may change the live network
+    /// directly instead of through a draft.
+    #[serde(default)]
+    pub live_edits: bool,
+}
+
+/// A request guard for writes straight to the live network, failing with 403
+/// unless `[revisions] live_edits` allows them.
+#[derive(Debug, Clone, Copy)]
+pub struct LiveEdit;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for LiveEdit {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, ()> {
+        match req.rocket().state::<RevisionConfig>() {
+            Some(config) if config.live_edits => Outcome::Success(LiveEdit),
+            Some(_) => Outcome::Failure((Status::Forbidden, ())),
+            None => Outcome::Failure((Status::InternalServerError, ())),
+        }
+    }
+}
+
+table! {
+    revisions (id) {
+        id -> Integer,
+        state -> Text,
+        note -> Text,
+        created_at -> BigInt,
+        published_at -> Nullable<BigInt>,
+        base -> Nullable<Text>,
+    }
+}
+
+table! {
+    revision_places (revision, busid) {
+        revision -> Integer,
+        busid -> Text,
+        latitude -> Float,
+        longitude -> Float,
+        name -> Text,
+    }
+}
+
+table! {
+    revision_routes (revision, routeid) {
+        revision -> Integer,
+        routeid -> Text,
+        placeid -> Text,
+        inbound -> Nullable<Text>,
+    }
+}
+
+table! {
+    place_location (busid) {
+        busid -> Text,
+        latitude -> Float,
+        longitude -> Float,
+        name -> Text,
+        deleted_at -> Nullable<BigInt>,
+    }
+}
+
+table! {
+    routes (routeid) {
+        routeid -> Text,
+        placeid -> Text,
+        inbound -> Nullable<Text>,
+        deleted_at -> Nullable<BigInt>,
+    }
+}
+
+table! {
+    vehicle_assignments (id) {
+        id -> Integer,
+        routeid -> Text,
+        valid_to -> Nullable<BigInt>,
+    }
+}
+
+fn core_options() -> CorsOptions {
+    rocket_cors::CorsOptions {
+        allowed_origins: AllowedOrigins::all(),
+        allowed_methods: vec![
+            http::Method::Get,
+            http::Method::Post,
+            http::Method::Options,
+            http::Method::Delete,
+            http::Method::Put,
+        ]
+        .into_iter()
+        .map(Method)
+        .collect(),
+        allowed_headers: AllowedHeaders::all(),
+        allow_credentials: true,
+        fairing_route_base: "/".to_owned(),
+        max_age: Some(42),
+        ..Default::default()
+    }
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() as i64)
+}
+
+#[derive(Debug, Clone, Serialize, Queryable)]
+#[serde(crate = "rocket::serde")]
+struct Revision {
+    id: i32,
+    state: String,
+    note: String,
+    created_at: i64,
+    published_at: Option<i64>,
+    /// The live network the draft was started from, as JSON.
+    #[serde(skip)]
+    base: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize, Queryable)]
+#[serde(crate = "rocket::serde")]
+struct Place {
+    busid: String,
+    latitude: f32,
+    longitude: f32,
+    name: String,
+}
+
+impl Place {
+    fn key(&self) -> &str {
+        &self.busid
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize, Queryable)]
+#[serde(crate = "rocket::serde")]
+struct Route {
+    routeid: String,
+    placeid: String,
+    inbound: Option<String>,
+}
+
+impl Route {
+    fn key(&self) -> &str {
+        &self.routeid
+    }
+
+    /// Every stop in both stop lists.
+    fn stops(&self) -> impl Iterator<Item = &str> {
+        self.placeid
+            .split('|')
+            .chain(self.inbound.iter().flat_map(|inbound| inbound.split('|')))
+    }
+}
+
+/// The body of `POST /admin/revisions/`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct RevisionIn {
+    #[serde(default)]
+    note: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct PlaceIn {
+    name: String,
+    latitude: f32,
+    longitude: f32,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct RouteIn {
+    placeid: String,
+    inbound: Option<String>,
+}
+
+/// Everything a revision holds.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+struct Network {
+    places: Vec<Place>,
+    routes: Vec<Route>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(crate = "rocket::serde")]
+struct RevisionOut {
+    #[serde(flatten)]
+    revision: Revision,
+    #[serde(flatten)]
+    network: Network,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(crate = "rocket::serde")]
+struct Changed<T> {
+    before: T,
+    after: T,
+}
+
+/// How the rows of one table differ between two networks.
+#[derive(Debug, Clone, Serialize)]
+#[serde(crate = "rocket::serde")]
+struct TableDiff<T> {
+    added: Vec<T>,
+    changed: Vec<Changed<T>>,
+    removed: Vec<T>,
+}
+
+impl<T: PartialEq> TableDiff<T> {
+    fn new(published: Vec<T>, draft: Vec<T>, key: fn(&T) -> &str) -> Self {
+        let mut published: BTreeMap<String, T> = published
+            .into_iter()
+            .map(|row| (key(&row).to_owned(), row))
+            .collect();
+        let mut diff = TableDiff {
+            added: vec![],
+            changed: vec![],
+            removed: vec![],
+        };
+        for row in draft {
+            match published.remove(key(&row)) {
+                None => diff.added.push(row),
+                Some(before) if before != row => diff.changed.push(Changed { before, after: row }),
+                Some(_) => {}
+            }
+        }
+        diff.removed = published.into_values().collect();
+        diff
+    }
+}
+
+/// What publishing a revision changes in the live network.
+#[derive(Debug, Clone, Serialize)]
+#[serde(crate = "rocket::serde")]
+struct Diff {
+    places: TableDiff<Place>,
+    routes: TableDiff<Route>,
+}
+
+impl Diff {
+    fn new(published: Network, draft: Network) -> Self {
+        Diff {
+            places: TableDiff::new(published.places, draft.places, Place::key),
+            routes: TableDiff::new(published.routes, draft.routes, Route::key),
+        }
+    }
+}
+
+/// A route of a revision stopping at places the revision doesn't have.
+#[derive(Debug, Clone, Serialize)]
+#[serde(crate = "rocket::serde")]
+struct MissingPlaces {
+    routeid: String,
+    missing: Vec<String>,
+}
+
+fn missing_places(network: &Network) -> Vec<MissingPlaces> {
+    let known: BTreeSet<&str> = network.places.iter().map(Place::key).collect();
+    network
+        .routes
+        .iter()
+        .filter_map(|route| {
+            let missing: BTreeSet<&str> =
+                route.stops().filter(|stop| !known.contains(stop)).collect();
+            (!missing.is_empty()).then(|| MissingPlaces {
+                routeid: route.routeid.clone(),
+                missing: missing.into_iter().map(str::to_owned).collect(),
+            })
+        })
+        .collect()
+}
+
+/// The network riders see now.
+fn live(conn: &mut db::Connection) -> QueryResult<Network> {
+    Ok(Network {
+        places: place_location::table
+            .select((
+                place_location::busid,
+                place_location::latitude,
+                place_location::longitude,
+                place_location::name,
+            ))
+            .filter(place_location::deleted_at.is_null())
+            .order(place_location::busid)
+            .load(conn)?,
+        routes: routes::table
+            .select((routes::routeid, routes::placeid, routes::inbound))
+            .filter(routes::deleted_at.is_null())
+            .order(routes::routeid)
+            .load(conn)?,
+    })
+}
+
+/// The network revision `id` holds.
+fn snapshot(conn: &mut db::Connection, id: i32) -> QueryResult<Network> {
+    Ok(Network {
+        places: revision_places::table
+            .select((
+                revision_places::busid,
+                revision_places::latitude,
+                revision_places::longitude,
+                revision_places::name,
+            ))
+            .filter(revision_places::revision.eq(id))
+            .order(revision_places::busid)
+            .load(conn)?,
+        routes: revision_routes::table
+            .select((
+                revision_routes::routeid,
+                revision_routes::placeid,
+                revision_routes::inbound,
+            ))
+            .filter(revision_routes::revision.eq(id))
+            .order(revision_routes::routeid)
+            .load(conn)?,
+    })
+}
+
+fn save_place(conn: &mut db::Connection, id: i32, place: &Place) -> QueryResult<usize> {
+    diesel::insert_into(revision_places::table)
+        .values((
+            revision_places::revision.eq(id),
+            revision_places::busid.eq(&place.busid),
+            revision_places::latitude.eq(place.latitude),
+            revision_places::longitude.eq(place.longitude),
+            revision_places::name.eq(&place.name),
+        ))
+        .on_conflict((revision_places::revision, revision_places::busid))
+        .do_update()
+        .set((
+            revision_places::latitude.eq(place.latitude),
+            revision_places::longitude.eq(place.longitude),
+            revision_places::name.eq(&place.name),
+        ))
+        .execute(conn)
+}
+
+fn save_route(conn: &mut db::Connection, id: i32, route: &Route) -> QueryResult<usize> {
+    diesel::insert_into(revision_routes::table)
+        .values((
+            revision_routes::revision.eq(id),
+            revision_routes::routeid.eq(&route.routeid),
+            revision_routes::placeid.eq(&route.placeid),
+            revision_routes::inbound.eq(route.inbound.as_deref()),
+        ))
+        .on_conflict((revision_routes::revision, revision_routes::routeid))
+        .do_update()
+        .set((
+            revision_routes::placeid.eq(&route.placeid),
+            revision_routes::inbound.eq(route.inbound.as_deref()),
+        ))
+        .execute(conn)
+}
+
+/// Adds a revision holding `network` and returns its id. A draft keeps
+/// `network` as its base too, the live network it started from.
+fn create(
+    conn: &mut db::Connection,
+    state: &str,
+    note: &str,
+    network: &Network,
+) -> QueryResult<i32> {
+    let base = (state == DRAFT).then(|| json::to_string(network).expect("network as JSON"));
+    let id: i32 = diesel::insert_into(revisions::table)
+        .values((
+            revisions::state.eq(state),
+            revisions::note.eq(note),
+            revisions::created_at.eq(now()),
+            revisions::base.eq(base),
+        ))
+        .returning(revisions::id)
+        .get_result(conn)?;
+    for place in &network.places {
+        save_place(conn, id, place)?;
+    }
+    for route in &network.routes {
+        save_route(conn, id, route)?;
+    }
+    Ok(id)
+}
+
+fn state(conn: &mut db::Connection, id: i32) -> QueryResult<Option<String>> {
+    revisions::table
+        .select(revisions::state)
+        .filter(revisions::id.eq(id))
+        .first(conn)
+        .optional()
+}
+
+/// Whether revision `id` can be edited; `None` when there is no such
+/// revision.
+fn editable(conn: &mut db::Connection, id: i32) -> QueryResult<Option<bool>> {
+    Ok(state(conn, id)?.map(|state| state == DRAFT))
+}
+
+/// Makes `network` the live one. Places and routes it leaves out are deleted
+/// the way `DELETE` deletes them, so they can still be restored, buses
+/// running a route that goes are taken off it, and `busses` is rewritten to
+/// match the routes.
+fn apply(conn: &mut db::Connection, network: &Network, at: i64) -> QueryResult<()> {
+    let keep: BTreeSet<&str> = network.places.iter().map(Place::key).collect();
+    let gone: Vec<String> = place_location::table
+        .select(place_location::busid)
+        .filter(place_location::deleted_at.is_null())
+        .load::<String>(conn)?
+        .into_iter()
+        .filter(|busid| !keep.contains(busid.as_str()))
+        .collect();
+    diesel::update(place_location::table)
+        .filter(place_location::busid.eq_any(&gone))
+        .set(place_location::deleted_at.eq(at))
+        .execute(conn)?;
+    for place in &network.places {
+        diesel::insert_into(place_location::table)
+            .values((
+                place_location::busid.eq(&place.busid),
+                place_location::latitude.eq(place.latitude),
+                place_location::longitude.eq(place.longitude),
+                place_location::name.eq(&place.name),
+            ))
+            .on_conflict(place_location::busid)
+            .do_update()
+            .set((
+                place_location::latitude.eq(place.latitude),
+                place_location::longitude.eq(place.longitude),
+                place_location::name.eq(&place.name),
+                place_location::deleted_at.eq(None::<i64>),
+            ))
+            .execute(conn)?;
+    }
+
+    let keep: BTreeSet<&str> = network.routes.iter().map(Route::key).collect();
+    let gone: Vec<String> = routes::table
+        .select(routes::routeid)
+        .filter(routes::deleted_at.is_null())
+        .load::<String>(conn)?
+        .into_iter()
+        .filter(|routeid| !keep.contains(routeid.as_str()))
+        .collect();
+    diesel::update(routes::table)
+        .filter(routes::routeid.eq_any(&gone))
+        .set(routes::deleted_at.eq(at))
+        .execute(conn)?;
+    diesel::update(vehicle_assignments::table)
+        .filter(vehicle_assignments::routeid.eq_any(&gone))
+        .filter(vehicle_assignments::valid_to.is_null())
+        .set(vehicle_assignments::valid_to.eq(at))
+        .execute(conn)?;
+    for route in &network.routes {
+        diesel::insert_into(routes::table)
+            .values((
+                routes::routeid.eq(&route.routeid),
+                routes::placeid.eq(&route.placeid),
+                routes::inbound.eq(route.inbound.as_deref()),
+            ))
+            .on_conflict(routes::routeid)
+            .do_update()
+            .set((
+                routes::placeid.eq(&route.placeid),
+                routes::inbound.eq(route.inbound.as_deref()),
+                routes::deleted_at.eq(None::<i64>),
+            ))
+            .execute(conn)?;
+    }
+
+    repair_busses(conn)
+}
+
+/// Why a revision was not published: the routes that would stop at places
+/// the revision doesn't have, or what changed in the live network since the
+/// revision was based on it.
+#[derive(Debug, Clone, Serialize)]
+#[serde(crate = "rocket::serde", untagged)]
+enum Refused {
+    MissingPlaces(Vec<MissingPlaces>),
+    LiveChanged(Diff),
+}
+
+/// The live network a publish of revision `id` expects to replace: a draft's
+/// base, or for a rollback what the published revision holds. `None` when
+/// there is nothing to compare with, such as for drafts started before bases
+/// were kept.
+fn expected(conn: &mut db::Connection, id: i32, from: &str) -> QueryResult<Option<Network>> {
+    if from == DRAFT {
+        let base: Option<String> = revisions::table
+            .select(revisions::base)
+            .filter(revisions::id.eq(id))
+            .first(conn)?;
+        return Ok(base.and_then(|base| json::from_str(&base).ok()));
+    }
+    let published: Option<i32> = revisions::table
+        .select(revisions::id)
+        .filter(revisions::state.eq(PUBLISHED))
+        .first(conn)
+        .optional()?;
+    published
+        .map(|published| snapshot(conn, published))
+        .transpose()
+}
+
+/// Publishes revision `id` if it is in state `from`, returning what changed
+/// in the live network or `None` when it isn't. Unless `force` is set, a live
+/// network edited since the revision was based on it is left alone.
+fn publish(
+    conn: &mut db::Connection,
+    id: i32,
+    from: &str,
+    action: &str,
+    force: bool,
+    actor: &Actor,
+) -> QueryResult<std::result::Result<Option<Diff>, Refused>> {
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        if state(conn, id)?.as_deref() != Some(from) {
+            return Ok(Ok(None));
+        }
+        let network = snapshot(conn, id)?;
+        let missing = missing_places(&network);
+        if !missing.is_empty() {
+            return Ok(Err(Refused::MissingPlaces(missing)));
+        }
+        let current = live(conn)?;
+        if !force {
+            if let Some(expected) = expected(conn, id, from)? {
+                if expected != current {
+                    return Ok(Err(Refused::LiveChanged(Diff::new(expected, current))));
+                }
+            }
+        }
+        let previous: Option<i32> = revisions::table
+            .select(revisions::id)
+            .filter(revisions::state.eq(PUBLISHED))
+            .first(conn)
+            .optional()?;
+        if previous.is_none() {
+            // Nothing was published from a revision yet; keep the network
+            // as it stands so that this publish can be rolled back too.
+            let note = format!("before revision {id}");
+            create(conn, RETIRED, &note, &current)?;
+        }
+        let at = now();
+        apply(conn, &network, at)?;
+        diesel::update(revisions::table)
+            .filter(revisions::state.eq(PUBLISHED))
+            .set(revisions::state.eq(RETIRED))
+            .execute(conn)?;
+        diesel::update(revisions::table)
+            .filter(revisions::id.eq(id))
+            .set((
+                revisions::state.eq(PUBLISHED),
+                revisions::published_at.eq(at),
+            ))
+            .execute(conn)?;
+        let diff = Diff::new(current, network);
+        Change::new(action, "revision", &id.to_string())
+            .before(&previous)
+            .after(&diff)
+            .record(conn, actor)?;
+        Ok(Ok(Some(diff)))
+    })
+}
+
+/// Revisions, newest first, paged with `?limit=` and `?offset=`.
+#[get("/?<paging..>")]
+async fn list<'r, 'o: 'r>(_admin: Admin, db: Db, paging: Paging) -> Result<impl Responder<'r, 'o>> {
+    let rows: Vec<Revision> = db
+        .run(move |conn| {
+            revisions::table
+                .order(revisions::id.desc())
+                .limit(paging.fetch())
+                .offset(paging.offset())
+                .load(conn)
+        })
+        .await?;
+
+    let out = paging.page(rows);
+    let options = match core_options().to_cors() {
+        Ok(a) => a,
+        Err(a) => return Ok(Err(a)),
+    };
+    Ok(options.respond_owned(move |guard| guard.responder(out)))
+}
+
+/// Starts a draft as a copy of the live network.
+#[post("/", data = "<post>")]
+async fn create_post<'r, 'o: 'r>(
+    _admin: Admin,
+    db: Db,
+    actor: Actor,
+    post: Json<RevisionIn>,
+) -> Result<impl Responder<'r, 'o>> {
+    let note = post.into_inner().note;
+    let revision: Revision = db
+        .write(move |conn| {
+            conn.transaction::<_, diesel::result::Error, _>(|conn| {
+                let network = live(conn)?;
+                let id = create(conn, DRAFT, &note, &network)?;
+                let revision: Revision =
+                    revisions::table.filter(revisions::id.eq(id)).first(conn)?;
+                Change::new("create", "revision", &id.to_string())
+                    .after(&revision)
+                    .record(conn, &actor)?;
+                Ok(revision)
+            })
+        })
+        .await?;
+    let out = Created::new(format!("/admin/revisions/{}", revision.id)).body(Json(revision));
+    let options = match core_options().to_cors() {
+        Ok(a) => a,
+        Err(a) => return Ok(Err(a)),
+    };
+    Ok(options.respond_owned(move |guard| guard.responder(out)))
+}
+
+/// A revision with the network it holds.
+#[get("/<id>")]
+async fn get_one<'r, 'o: 'r>(_admin: Admin, db: Db, id: i32) -> Result<impl Responder<'r, 'o>> {
+    let out = db
+        .run(move |conn| {
+            let revision: Option<Revision> = revisions::table
+                .filter(revisions::id.eq(id))
+                .first(conn)
+                .optional()?;
+            let revision = match revision {
+                Some(revision) => revision,
+                None => return Ok::<_, diesel::result::Error>(None),
+            };
+            let network = snapshot(conn, id)?;
+            Ok(Some(Json(RevisionOut { revision, network })))
+        })
+        .await?;
+    let options = match core_options().to_cors() {
+        Ok(a) => a,
+        Err(a) => return Ok(Err(a)),
+    };
+    Ok(options.respond_owned(move |guard| guard.responder(out)))
+}
+
+/// What publishing the revision would change in the live network: places and
+/// routes added, changed and removed.
+#[get("/<id>/diff")]
+async fn diff<'r, 'o: 'r>(_admin: Admin, db: Db, id: i32) -> Result<impl Responder<'r, 'o>> {
+    let out = db
+        .run(move |conn| {
+            if state(conn, id)?.is_none() {
+                return Ok::<_, diesel::result::Error>(None);
+            }
+            let published = live(conn)?;
+            let draft = snapshot(conn, id)?;
+            Ok(Some(Json(Diff::new(published, draft))))
+        })
+        .await?;
+    let options = match core_options().to_cors() {
+        Ok(a) => a,
+        Err(a) => return Ok(Err(a)),
+    };
+    Ok(options.respond_owned(move |guard| guard.responder(out)))
+}
+
+/// Sets a place in a draft, answering 201 or 200. 409 when the revision is
+/// not a draft.
+#[put("/<id>/places/<placeid>", data = "<post>")]
+async fn put_place<'r, 'o: 'r>(
+    _admin: Admin,
+    db: Db,
+    actor: Actor,
+    id: i32,
+    placeid: String,
+    post: Json<PlaceIn>,
+) -> Result<impl Responder<'r, 'o>> {
+    let post_value = post.into_inner();
+    let place = Place {
+        busid: placeid,
+        latitude: post_value.latitude,
+        longitude: post_value.longitude,
+        name: post_value.name,
+    };
+    let out = db
+        .write(move |conn| {
+            conn.transaction::<_, diesel::result::Error, _>(|conn| {
+                match editable(conn, id)? {
+                    None => return Ok(Ok(None)),
+                    Some(false) => return Ok(Err(Conflict(Some("only drafts can be edited")))),
+                    Some(true) => {}
+                }
+                let before: Option<Place> = revision_places::table
+                    .select((
+                        revision_places::busid,
+                        revision_places::latitude,
+                        revision_places::longitude,
+                        revision_places::name,
+                    ))
+                    .filter(revision_places::revision.eq(id))
+                    .filter(revision_places::busid.eq(&place.busid))
+                    .first(conn)
+                    .optional()?;
+                save_place(conn, id, &place)?;
+                let action = if before.is_some() { "update" } else { "create" };
+                Change::new(action, "revision_place", &format!("{id}/{}", place.busid))
+                    .before(&before)
+                    .after(&place)
+                    .record(conn, &actor)?;
+                let status = if before.is_some() {
+                    Status::Ok
+                } else {
+                    Status::Created
+                };
+                Ok(Ok(Some(Custom(status, Json(place)))))
+            })
+        })
+        .await?;
+    let options = match core_options().to_cors() {
+        Ok(a) => a,
+        Err(a) => return Ok(Err(a)),
+    };
+    Ok(options.respond_owned(move |guard| guard.responder(out)))
+}
+
+#[delete("/<id>/places/<placeid>")]
+async fn delete_place<'r, 'o: 'r>(
+    _admin: Admin,
+    db: Db,
+    actor: Actor,
+    id: i32,
+    placeid: String,
+) -> Result<impl Responder<'r, 'o>> {
+    let out = db
+        .write(move |conn| {
+            conn.transaction::<_, diesel::result::Error, _>(|conn| {
+                match editable(conn, id)? {
+                    None => return Ok(Ok(None)),
+                    Some(false) => return Ok(Err(Conflict(Some("only drafts can be edited")))),
+                    Some(true) => {}
+                }
+                let before: Option<Place> = revision_places::table
+                    .select((
+                        revision_places::busid,
+                        revision_places::latitude,
+                        revision_places::longitude,
+                        revision_places::name,
+                    ))
+                    .filter(revision_places::revision.eq(id))
+                    .filter(revision_places::busid.eq(&placeid))
+                    .first(conn)
+                    .optional()?;
+                let before = match before {
+                    Some(before) => before,
+                    None => return Ok(Ok(None)),
+                };
+                diesel::delete(revision_places::table)
+                    .filter(revision_places::revision.eq(id))
+                    .filter(revision_places::busid.eq(&placeid))
+                    .execute(conn)?;
+                Change::new("delete", "revision_place", &format!("{id}/{placeid}"))
+                    .before(&before)
+                    .record(conn, &actor)?;
+                Ok(Ok(Some(())))
+            })
+        })
+        .await?;
+    let options = match core_options().to_cors() {
+        Ok(a) => a,
+        Err(a) => return Ok(Err(a)),
+    };
+    Ok(options.respond_owned(move |guard| guard.responder(out)))
+}
+
+/// Sets the stop lists of a route in a draft, answering 201 or 200. 409 when
+/// the revision is not a draft.
+#[put("/<id>/routes/<routeid>", data = "<post>")]
+async fn put_route<'r, 'o: 'r>(
+    _admin: Admin,
+    db: Db,
+    actor: Actor,
+    id: i32,
+    routeid: String,
+    post: Json<RouteIn>,
+) -> Result<impl Responder<'r, 'o>> {
+    let post_value = post.into_inner();
+    let route = Route {
+        routeid,
+        placeid: post_value.placeid,
+        inbound: post_value.inbound,
+    };
+    let out = db
+        .write(move |conn| {
+            conn.transaction::<_, diesel::result::Error, _>(|conn| {
+                match editable(conn, id)? {
+                    None => return Ok(Ok(None)),
+                    Some(false) => return Ok(Err(Conflict(Some("only drafts can be edited")))),
+                    Some(true) => {}
+                }
+                let before: Option<Route> = revision_routes::table
+                    .select((
+                        revision_routes::routeid,
+                        revision_routes::placeid,
+                        revision_routes::inbound,
+                    ))
+                    .filter(revision_routes::revision.eq(id))
+                    .filter(revision_routes::routeid.eq(&route.routeid))
+                    .first(conn)
+                    .optional()?;
+                save_route(conn, id, &route)?;
+                let action = if before.is_some() { "update" } else { "create" };
+                Change::new(action, "revision_route", &format!("{id}/{}", route.routeid))
+                    .before(&before)
+                    .after(&route)
+                    .record(conn, &actor)?;
+                let status = if before.is_some() {
+                    Status::Ok
+                } else {
+                    Status::Created
+                };
+                Ok(Ok(Some(Custom(status, Json(route)))))
+            })
+        })
+        .await?;
+    let options = match core_options().to_cors() {
+        Ok(a) => a,
+        Err(a) => return Ok(Err(a)),
+    };
+    Ok(options.respond_owned(move |guard| guard.responder(out)))
+}
+
+#[delete("/<id>/routes/<routeid>")]
+async fn delete_route<'r, 'o: 'r>(
+    _admin: Admin,
+    db: Db,
+    actor: Actor,
+    id: i32,
+    routeid: String,
+) -> Result<impl Responder<'r, 'o>> {
+    let out = db
+        .write(move |conn| {
+            conn.transaction::<_, diesel::result::Error, _>(|conn| {
+                match editable(conn, id)? {
+                    None => return Ok(Ok(None)),
+                    Some(false) => return Ok(Err(Conflict(Some("only drafts can be edited")))),
+                    Some(true) => {}
+                }
+                let before: Option<Route> = revision_routes::table
+                    .select((
+                        revision_routes::routeid,
+                        revision_routes::placeid,
+                        revision_routes::inbound,
+                    ))
+                    .filter(revision_routes::revision.eq(id))
+                    .filter(revision_routes::routeid.eq(&routeid))
+                    .first(conn)
+                    .optional()?;
+                let before = match before {
+                    Some(before) => before,
+                    None => return Ok(Ok(None)),
+                };
+                diesel::delete(revision_routes::table)
+                    .filter(revision_routes::revision.eq(id))
+                    .filter(revision_routes::routeid.eq(&routeid))
+                    .execute(conn)?;
+                Change::new("delete", "revision_route", &format!("{id}/{routeid}"))
+                    .before(&before)
+                    .record(conn, &actor)?;
+                Ok(Ok(Some(())))
+            })
+        })
+        .await?;
+    let options = match core_options().to_cors() {
+        Ok(a) => a,
+        Err(a) => return Ok(Err(a)),
+    };
+    Ok(options.respond_owned(move |guard| guard.responder(out)))
+}
+
+/// Throws a draft away. 409 when the revision is not a draft; published and
+/// retired revisions are kept for rollbacks.
+#[delete("/<id>")]
+async fn delete_one<'r, 'o: 'r>(
+    _admin: Admin,
+    db: Db,
+    actor: Actor,
+    id: i32,
+) -> Result<impl Responder<'r, 'o>> {
+    let out = db
+        .write(move |conn| {
+            conn.transaction::<_, diesel::result::Error, _>(|conn| {
+                match editable(conn, id)? {
+                    None => return Ok(Ok(None)),
+                    Some(false) => return Ok(Err(Conflict(Some("only drafts can be deleted")))),
+                    Some(true) => {}
+                }
+                let before: Revision = revisions::table.filter(revisions::id.eq(id)).first(conn)?;
+                diesel::delete(revision_places::table)
+                    .filter(revision_places::revision.eq(id))
+                    .execute(conn)?;
+                diesel::delete(revision_routes::table)
+                    .filter(revision_routes::revision.eq(id))
+                    .execute(conn)?;
+                diesel::delete(revisions::table)
+                    .filter(revisions::id.eq(id))
+                    .execute(conn)?;
+                Change::new("delete", "revision", &id.to_string())
+                    .before(&before)
+                    .record(conn, &actor)?;
+                Ok(Ok(Some(())))
+            })
+        })
+        .await?;
+    let options = match core_options().to_cors() {
+        Ok(a) => a,
+        Err(a) => return Ok(Err(a)),
+    };
+    Ok(options.respond_owned(move |guard| guard.responder(out)))
+}
+
+/// Makes a draft the live network in one transaction and answers with what
+/// changed. 404 unless the revision is a draft; 409 with the routes at fault
+/// when a route stops at a place the draft doesn't have, or with what changed
+/// live since the draft was started unless `?force=true`.
+#[post("/<id>/publish?<force>")]
+async fn publish_post<'r, 'o: 'r>(
+    _admin: Admin,
+    db: Db,
+    actor: Actor,
+    id: i32,
+    force: Option<bool>,
+) -> Result<impl Responder<'r, 'o>> {
+    let force = force.unwrap_or(false);
+    let out = db
+        .write(move |conn| publish(conn, id, DRAFT, "publish", force, &actor))
+        .await?;
+    let out = out
+        .map(|diff| diff.map(Json))
+        .map_err(|refused| Conflict(Some(Json(refused))));
+    let options = match core_options().to_cors() {
+        Ok(a) => a,
+        Err(a) => return Ok(Err(a)),
+    };
+    Ok(options.respond_owned(move |guard| guard.responder(out)))
+}
+
+/// Publishes again a revision that was live before and answers with what
+/// changed. 404 unless the revision is retired; 409 with what changed live
+/// since the last publish unless `?force=true`.
+#[post("/<id>/rollback?<force>")]
+async fn rollback_post<'r, 'o: 'r>(
+    _admin: Admin,
+    db: Db,
+    actor: Actor,
+    id: i32,
+    force: Option<bool>,
+) -> Result<impl Responder<'r, 'o>> {
+    let force = force.unwrap_or(false);
+    let out = db
+        .write(move |conn| publish(conn, id, RETIRED, "rollback", force, &actor))
+        .await?;
+    let out = out
+        .map(|diff| diff.map(Json))
+        .map_err(|refused| Conflict(Some(Json(refused))));
+    let options = match core_options().to_cors() {
+        Ok(a) => a,
+        Err(a) => return Ok(Err(a)),
+    };
+    Ok(options.respond_owned(move |guard| guard.responder(out)))
+}
+
+async fn run_migrations(rocket: Rocket<Build>) -> Rocket<Build> {
+    use diesel_migrations::MigrationHarness;
+
+    Db::get_one(&rocket)
+        .await
+        .expect("database connection")
+        .run(|conn| {
+            conn.run_pending_migrations(db::MIGRATIONS)
+                .expect("diesel migrations");
+        })
+        .await;
+
+    rocket
+}
+
+pub fn revision_data() -> AdHoc {
+    AdHoc::try_on_ignite("Network revisions", |rocket| async {
+        let config: RevisionConfig = match config::read(&rocket, "revisions") {
+            Some(config) => config,
+            None => return Err(rocket),
+        };
+        Ok(rocket
+            .manage(config)
+            .attach(AdHoc::on_ignite("Diesel Migrations", run_migrations))
+            .mount(
+                "/admin/revisions",
+                routes![
+                    list,
+                    create_post,
+                    get_one,
+                    diff,
+                    put_place,
+                    delete_place,
+                    put_route,
+                    delete_route,
+                    delete_one,
+                    publish_post,
+                    rollback_post
+                ],
+            ))
+    })
+}
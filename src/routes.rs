@@ -1,14 +1,32 @@
 use rocket::fairing::AdHoc;
-use rocket::response::{status::Created, Debug, Responder};
+use rocket::http::Status;
+use rocket::response::{
+    status::{BadRequest, Conflict, Created, Custom},
+    Debug, Responder,
+};
 use rocket::serde::{json::Json, Deserialize, Serialize};
-use rocket::{http, Build, Rocket};
+use rocket::{http, Build, Orbit, Rocket, State};
 
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use self::diesel::dsl::sql;
 use self::diesel::prelude::*;
+use self::diesel::sql_types::{Bool, Text};
 use rocket_cors::{AllowedHeaders, AllowedOrigins, CorsOptions, Method};
 use rocket_sync_db_pools::diesel;
 
-#[database("diesel")]
-struct Db(diesel::SqliteConnection);
+use crate::admin::IncludeDeleted;
+use crate::audit::{Actor, Change};
+use crate::bus::{self, Positions};
+use crate::cache::{Conditional, ResponseCache};
+use crate::config;
+use crate::db::{self, Db};
+use crate::geo;
+use crate::locale::{self, Languages};
+use crate::paging::{Paging, Sort};
+use crate::retention::RetentionConfig;
+use crate::revisions::LiveEdit;
 
 type Result<T, E = Debug<diesel::result::Error>> = std::result::Result<T, E>;
 
@@ -20,6 +38,8 @@ fn core_options() -> CorsOptions {
             http::Method::Post,
             http::Method::Options,
             http::Method::Delete,
+            http::Method::Put,
+            http::Method::Patch,
         ]
         .into_iter()
         .map(Method)
@@ -32,12 +52,64 @@ fn core_options() -> CorsOptions {
     }
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize, Queryable, Insertable)]
+#[derive(Debug, Clone, Deserialize, Serialize, Queryable, Insertable, AsChangeset)]
 #[serde(crate = "rocket::serde")]
-#[diesel(table_name = routes)]
+#[diesel(table_name = routes, treat_none_as_null = true)]
 struct Routes {
-    busid: String,
+    routeid: String,
     placeid: String,
+    inbound: Option<String>,
+    /// When the route was deleted; it can be restored until it is purged.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    deleted_at: Option<i64>,
+}
+
+impl Routes {
+    /// Every stop the route serves, in either direction.
+    fn all_stops(&self) -> Vec<String> {
+        let mut stops = self.stops(Direction::Outbound);
+        stops.extend(self.stops(Direction::Inbound));
+        stops
+    }
+
+    /// Stops in the order the bus serves them when running `direction`. A
+    /// route without an explicit inbound pattern runs its stops in reverse.
+    fn stops(&self, direction: Direction) -> Vec<String> {
+        let outbound = self.placeid.split('|').map(|a| a.to_owned());
+        match (direction, &self.inbound) {
+            (Direction::Outbound, _) => outbound.collect(),
+            (Direction::Inbound, Some(inbound)) => {
+                inbound.split('|').map(|a| a.to_owned()).collect()
+            }
+            (Direction::Inbound, None) => outbound.rev().collect(),
+        }
+    }
+}
+
+/// Which way a bus runs along its route. Outbound follows `routes.placeid`
+/// and the route shape, inbound runs back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, FromFormField)]
+#[serde(crate = "rocket::serde", rename_all = "lowercase")]
+pub enum Direction {
+    Outbound,
+    Inbound,
+}
+
+impl Direction {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Direction::Outbound => "outbound",
+            Direction::Inbound => "inbound",
+        }
+    }
+
+    pub fn parse(direction: &str) -> Option<Self> {
+        match direction {
+            "outbound" => Some(Direction::Outbound),
+            "inbound" => Some(Direction::Inbound),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize, Queryable, Insertable)]
@@ -47,6 +119,7 @@ struct PlaceLocation {
     busid: String,
     latitude: f32,
     longitude: f32,
+    deleted_at: Option<i64>,
 }
 
 table! {
@@ -54,34 +127,64 @@ table! {
         busid -> Text,
         latitude -> Float,
         longitude -> Float,
+        deleted_at -> Nullable<BigInt>,
     }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(crate = "rocket::serde")]
 struct Routing {
-    busid: String,
-    places: Vec<(String, f32, f32)>,
+    routeid: String,
+    buses: Vec<String>,
+    direction: Direction,
+    /// Stops in running order: id, latitude, longitude and the name in the
+    /// client's language.
+    places: Vec<(String, f32, f32, String)>,
+    /// Stops naming places that do not exist or are deleted, which `places`
+    /// cannot show; see `/routes/missing_places`.
+    missing: Vec<String>,
+    shape: Option<Vec<(f32, f32)>>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(crate = "rocket::serde")]
 struct RoutesIn {
     busid: String,
+    routeid: Option<String>,
     placeid: String,
+    inbound: Option<String>,
     latitude: f32,
     longitude: f32,
 }
 
 // Shimla || Sundernager || Ner Chock || Mandi
 table! {
-    routes (busid) {
-        busid -> Text,
+    routes (routeid) {
+        routeid -> Text,
         placeid -> Text,
+        inbound -> Nullable<Text>,
+        deleted_at -> Nullable<BigInt>,
     }
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize, Queryable, Insertable)]
+/// Road geometry of a route, stored as a `|`-joined list of `lat,lon` points
+/// like the stop list in `routes.placeid`.
+#[derive(Debug, Clone, Deserialize, Serialize, Queryable, Insertable, AsChangeset)]
+#[serde(crate = "rocket::serde")]
+#[diesel(table_name = route_shapes)]
+struct RouteShapes {
+    routeid: String,
+    shape: String,
+}
+
+table! {
+    route_shapes (routeid) {
+        routeid -> Text,
+        shape -> Text,
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, Queryable, Insertable, AsChangeset)]
 #[serde(crate = "rocket::serde")]
 #[diesel(table_name = busses)]
 struct Busses {
@@ -96,7 +199,7 @@ table! {
     }
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize, Queryable, Insertable)]
+#[derive(Debug, Clone, Deserialize, Serialize, Queryable, Insertable, AsChangeset)]
 #[serde(crate = "rocket::serde")]
 #[diesel(table_name = current_location)]
 struct CurrentLocation {
@@ -110,62 +213,484 @@ table! {
         busid -> Text,
         latitude -> Float,
         longitude -> Float,
+        direction -> Nullable<Text>,
+    }
+}
+
+/// A period during which a bus runs a route. `valid_to` stays empty while the
+/// assignment is active; closed assignments are kept so past trips can still
+/// be attributed to their route.
+#[derive(Debug, Clone, Deserialize, Serialize, Queryable, Insertable)]
+#[serde(crate = "rocket::serde")]
+#[diesel(table_name = vehicle_assignments)]
+struct VehicleAssignments {
+    busid: String,
+    routeid: String,
+    valid_from: i64,
+    valid_to: Option<i64>,
+}
+
+table! {
+    vehicle_assignments (id) {
+        id -> Integer,
+        busid -> Text,
+        routeid -> Text,
+        valid_from -> BigInt,
+        valid_to -> Nullable<BigInt>,
+    }
+}
+
+allow_tables_to_appear_in_same_query!(routes, vehicle_assignments);
+
+/// Stop lists of a route, as replaced by `PUT /routes/<id>`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct RouteBody {
+    placeid: String,
+    inbound: Option<String>,
+}
+
+/// A partial update of a route. Setting `routeid` renames it; an empty
+/// `inbound` clears the inbound pattern.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct RoutePatch {
+    routeid: Option<String>,
+    placeid: Option<String>,
+    inbound: Option<String>,
+}
+
+table! {
+    location_history (id) {
+        id -> Integer,
+        routeid -> Nullable<Text>,
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct AssignIn {
+    busid: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct SwapIn {
+    from: String,
+    to: String,
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+/// A place whose `busses` entry disagrees with the routes its buses run.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct Drift {
+    placeid: String,
+    /// Buses running a route through the place but not listed there.
+    missing: Vec<String>,
+    /// Buses listed at the place without running a route through it.
+    stale: Vec<String>,
+}
+
+/// Which buses each place should list: those actively assigned to a route
+/// that serves it, in assignment order.
+fn expected_busses(conn: &mut db::Connection) -> QueryResult<BTreeMap<String, Vec<String>>> {
+    let routes: Vec<Routes> = routes::table
+        .filter(routes::deleted_at.is_null())
+        .load(conn)?;
+    let assignments: Vec<(String, String)> = vehicle_assignments::table
+        .select((vehicle_assignments::busid, vehicle_assignments::routeid))
+        .filter(vehicle_assignments::valid_to.is_null())
+        .order(vehicle_assignments::valid_from)
+        .load(conn)?;
+    let mut expected: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for (busid, routeid) in assignments {
+        let route = match routes.iter().find(|route| route.routeid == routeid) {
+            Some(route) => route,
+            None => continue,
+        };
+        for place in route.all_stops() {
+            let buses = expected.entry(place).or_default();
+            if !buses.contains(&busid) {
+                buses.push(busid.clone());
+            }
+        }
+    }
+    Ok(expected)
+}
+
+/// Compares the `busses` entries of `places` (all places when `None`) with
+/// the active route assignments and, when `repair` is set, rewrites them to
+/// match. Listed buses keep their order; missing ones are appended.
+fn reconcile(
+    conn: &mut db::Connection,
+    places: Option<&[String]>,
+    repair: bool,
+) -> QueryResult<Vec<Drift>> {
+    let expected = expected_busses(conn)?;
+    let mut listed: BTreeMap<String, Vec<String>> = busses::table
+        .load::<Busses>(conn)?
+        .into_iter()
+        .map(|entry| {
+            let buses = entry
+                .busid
+                .split('|')
+                .filter(|bus| !bus.is_empty())
+                .map(|bus| bus.to_owned())
+                .collect();
+            (entry.placeid, buses)
+        })
+        .collect();
+    let mut placeids: BTreeSet<String> = expected.keys().chain(listed.keys()).cloned().collect();
+    if let Some(places) = places {
+        placeids.retain(|placeid| places.contains(placeid));
+    }
+    let mut drift = vec![];
+    for placeid in placeids {
+        let want = expected.get(&placeid).cloned().unwrap_or_default();
+        let have = listed.remove(&placeid).unwrap_or_default();
+        let missing: Vec<String> = want
+            .iter()
+            .filter(|bus| !have.contains(bus))
+            .cloned()
+            .collect();
+        let stale: Vec<String> = have
+            .iter()
+            .filter(|bus| !want.contains(bus))
+            .cloned()
+            .collect();
+        if missing.is_empty() && stale.is_empty() {
+            continue;
+        }
+        if repair {
+            let buses: Vec<String> = have
+                .into_iter()
+                .filter(|bus| want.contains(bus))
+                .chain(missing.iter().cloned())
+                .collect();
+            if buses.is_empty() {
+                diesel::delete(busses::table)
+                    .filter(busses::placeid.eq(&placeid))
+                    .execute(conn)?;
+            } else {
+                let row = Busses {
+                    placeid: placeid.clone(),
+                    busid: buses.join("|"),
+                };
+                diesel::insert_into(busses::table)
+                    .values(&row)
+                    .on_conflict(busses::placeid)
+                    .do_update()
+                    .set(&row)
+                    .execute(conn)?;
+            }
+        }
+        drift.push(Drift {
+            placeid,
+            missing,
+            stale,
+        });
     }
+    Ok(drift)
+}
+
+/// Rewrites every `busses` entry to match the active route assignments, for
+/// writers that replace routes wholesale.
+pub(crate) fn repair_busses(conn: &mut db::Connection) -> QueryResult<()> {
+    reconcile(conn, None, true).map(|_| ())
 }
 
+/// Buses currently running `routeid`.
+fn active_buses(conn: &mut db::Connection, routeid: &str) -> QueryResult<Vec<String>> {
+    vehicle_assignments::table
+        .select(vehicle_assignments::busid)
+        .filter(vehicle_assignments::routeid.eq(routeid))
+        .filter(vehicle_assignments::valid_to.is_null())
+        .order(vehicle_assignments::valid_from)
+        .load(conn)
+}
+
+/// Moves `busid` onto `route`, closing whatever assignment it had before. A
+/// bus without a position yet is placed at the route's first stop so that
+/// `/bus` accepts its fixes.
+fn assign(
+    conn: &mut db::Connection,
+    busid: &str,
+    route: &Routes,
+    actor: &Actor,
+) -> QueryResult<()> {
+    let current: Option<String> = vehicle_assignments::table
+        .select(vehicle_assignments::routeid)
+        .filter(vehicle_assignments::busid.eq(busid))
+        .filter(vehicle_assignments::valid_to.is_null())
+        .first(conn)
+        .optional()?;
+    if current.as_deref() == Some(&*route.routeid) {
+        return Ok(());
+    }
+    Change::new("assign", "vehicle_assignment", busid)
+        .before(&current)
+        .after(&route.routeid)
+        .record(conn, actor)?;
+    let mut places = route.all_stops();
+    if let Some(current) = current {
+        let previous: Option<Routes> = routes::table
+            .filter(routes::routeid.eq(current))
+            .first(conn)
+            .optional()?;
+        places.extend(previous.iter().flat_map(Routes::all_stops));
+    }
+    let at = now();
+    diesel::update(vehicle_assignments::table)
+        .filter(vehicle_assignments::busid.eq(busid))
+        .filter(vehicle_assignments::valid_to.is_null())
+        .set(vehicle_assignments::valid_to.eq(at))
+        .execute(conn)?;
+    diesel::insert_into(vehicle_assignments::table)
+        .values(VehicleAssignments {
+            busid: busid.to_owned(),
+            routeid: route.routeid.clone(),
+            valid_from: at,
+            valid_to: None,
+        })
+        .execute(conn)?;
+    reconcile(conn, Some(&places), true)?;
+    let start: Option<PlaceLocation> = match route.stops(Direction::Outbound).first() {
+        Some(stop) => place_location::table
+            .filter(place_location::busid.eq(stop))
+            .filter(place_location::deleted_at.is_null())
+            .first(conn)
+            .optional()?,
+        None => None,
+    };
+    let (latitude, longitude) = start.map_or((0.0, 0.0), |p| (p.latitude, p.longitude));
+    diesel::insert_into(current_location::table)
+        .values(CurrentLocation {
+            busid: busid.to_owned(),
+            latitude,
+            longitude,
+        })
+        .on_conflict_do_nothing()
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Closes the active assignment of `busid` to `route` and takes the bus off
+/// the route's places, returning whether there was one.
+fn unassign(
+    conn: &mut db::Connection,
+    busid: &str,
+    route: &Routes,
+    actor: &Actor,
+) -> QueryResult<bool> {
+    let closed = diesel::update(vehicle_assignments::table)
+        .filter(vehicle_assignments::busid.eq(busid))
+        .filter(vehicle_assignments::routeid.eq(&route.routeid))
+        .filter(vehicle_assignments::valid_to.is_null())
+        .set(vehicle_assignments::valid_to.eq(now()))
+        .execute(conn)?;
+    if closed > 0 {
+        Change::new("unassign", "vehicle_assignment", busid)
+            .before(&route.routeid)
+            .record(conn, actor)?;
+    }
+    reconcile(conn, Some(&route.all_stops()), true)?;
+    Ok(closed > 0)
+}
+
+/// Creates or replaces a route and puts `busid` on it. The route id defaults
+/// to the bus id, so one-bus routes can still be posted in one go. A route
+/// stopping at places that do not exist is refused with 422 and their ids.
 #[post("/", data = "<post>")]
-async fn bus_post<'r, 'o: 'r>(db: Db, post: Json<RoutesIn>) -> Result<impl Responder<'r, 'o>> {
+async fn bus_post<'r, 'o: 'r>(
+    _live: LiveEdit,
+    db: Db,
+    actor: Actor,
+    positions: &State<Positions>,
+    post: Json<RoutesIn>,
+) -> Result<impl Responder<'r, 'o>> {
+    let positions = positions.inner().clone();
     let post_value = post.clone();
-    let loc_value = CurrentLocation {
-        busid: post_value.busid.clone(),
-        latitude: post_value.latitude,
-        longitude: post_value.longitude,
-    };
+    let (latitude, longitude) = (post_value.latitude, post_value.longitude);
+    let busid = post_value.busid.clone();
     let post_value = Routes {
-        busid: post_value.busid.clone(),
+        routeid: post_value.routeid.unwrap_or(post_value.busid),
         placeid: post_value.placeid.clone(),
+        inbound: post_value.inbound.clone(),
+        deleted_at: None,
     };
-    db.run(move |conn| {
-        let post_double = post_value.clone();
-        let busid = post_double.busid;
-        let places = post_double.placeid;
-        let place = places.split('|').map(|p| p.to_owned());
-        'placing: for i in place {
-            if let Ok(place_entry) = busses::table
-                .filter(busses::placeid.eq(i.clone()))
-                .first::<Busses>(conn)
-            {
-                let bus_list: Busses = place_entry;
-                let bus_exist = bus_list.busid.split('|').any(|bus| bus == busid);
-                if bus_exist {
-                    continue 'placing;
-                } else {
-                    let busid = format!("{}|{}", bus_list.busid, busid);
-                    let a = Busses { placeid: i, busid };
-                    diesel::replace_into(busses::table)
-                        .values(a)
-                        .execute(conn)?;
+    let out = db
+        .write(move |conn| {
+            conn.transaction::<_, diesel::result::Error, _>(|conn| {
+                if let Some(refused) = refuse_unknown_stops(conn, &post_value)? {
+                    return Ok(Err(refused));
                 }
-            } else {
-                let a = Busses {
-                    placeid: i,
-                    busid: busid.to_owned(),
+                let previous: Option<Routes> = routes::table
+                    .filter(routes::routeid.eq(&post_value.routeid))
+                    .filter(routes::deleted_at.is_null())
+                    .first(conn)
+                    .optional()?;
+                // An upsert rather than REPLACE, which PostgreSQL does not
+                // have. It also updates the row in place where SQLite's
+                // REPLACE deletes and reinserts it, so nothing keyed on the
+                // old row sees a delete.
+                diesel::insert_into(routes::table)
+                    .values(&post_value)
+                    .on_conflict(routes::routeid)
+                    .do_update()
+                    .set(&post_value)
+                    .execute(conn)?;
+                let action = if previous.is_some() {
+                    "update"
+                } else {
+                    "create"
                 };
-                diesel::insert_into(busses::table).values(a).execute(conn)?;
-            }
+                Change::new(action, "route", &post_value.routeid)
+                    .before(&previous)
+                    .after(&post_value)
+                    .record(conn, &actor)?;
+                assign(conn, &busid, &post_value, &actor)?;
+                let mut places = post_value.all_stops();
+                places.extend(previous.iter().flat_map(Routes::all_stops));
+                reconcile(conn, Some(&places), true)?;
+                // Last, so the position only changes in memory once the
+                // route writes have all gone through.
+                bus::relocate(conn, &positions, &busid, latitude, longitude)?;
+                Ok(Ok(()))
+            })
+        })
+        .await?
+        .map(|()| Created::new("/").body(post));
+    let options = match core_options().to_cors() {
+        Ok(a) => a,
+        Err(a) => return Ok(Err(a)),
+    };
+    Ok(options.respond_owned(move |guard| guard.responder(out)))
+}
+
+/// A GeoJSON `LineString` geometry. Positions are `[lon, lat]` and may carry
+/// an altitude, which is ignored.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+struct LineString {
+    #[serde(rename = "type")]
+    kind: String,
+    coordinates: Vec<Vec<f32>>,
+}
+
+impl LineString {
+    fn new(points: &[(f32, f32)]) -> Self {
+        LineString {
+            kind: "LineString".to_owned(),
+            coordinates: points.iter().map(|(lat, lon)| vec![*lon, *lat]).collect(),
         }
-        match diesel::replace_into(routes::table)
-            .values(post_value)
-            .execute(conn)
-        {
-            Ok(_) => diesel::replace_into(current_location::table)
-                .values(loc_value)
-                .execute(conn),
-            Err(a) => Err(a),
+    }
+
+    fn points(&self) -> Option<Vec<(f32, f32)>> {
+        if self.kind != "LineString" {
+            return None;
         }
-    })
-    .await?;
-    let out = Created::new("/").body(post);
+        self.coordinates
+            .iter()
+            .map(|position| match position[..] {
+                [lon, lat, ..] => Some((lat, lon)),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+struct EncodedShape {
+    polyline: String,
+}
+
+/// Accepted upload formats for a route shape.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde", untagged)]
+enum ShapeIn {
+    GeoJson(LineString),
+    Encoded(EncodedShape),
+    Points(Vec<(f32, f32)>),
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(crate = "rocket::serde", untagged)]
+enum ShapeOut {
+    GeoJson(LineString),
+    Encoded(EncodedShape),
+}
+
+impl ShapeIn {
+    fn points(&self) -> Option<Vec<(f32, f32)>> {
+        let points = match self {
+            ShapeIn::GeoJson(line) => line.points()?,
+            ShapeIn::Encoded(encoded) => geo::decode_polyline(&encoded.polyline)?,
+            ShapeIn::Points(points) => points.clone(),
+        };
+        (points.len() >= 2).then_some(points)
+    }
+}
+
+/// Stores the road geometry of a route, uploaded as a GeoJSON `LineString`,
+/// an encoded polyline (`{"polyline": "..."}`) or a plain list of
+/// `(latitude, longitude)` points. Bus positions are snapped onto it.
+#[post("/<id>/shape", data = "<post>")]
+async fn shape_post<'r, 'o: 'r>(
+    db: Db,
+    actor: Actor,
+    id: String,
+    post: Json<ShapeIn>,
+) -> Result<impl Responder<'r, 'o>> {
+    let out = match post.points() {
+        Some(points) => {
+            let shape = RouteShapes {
+                routeid: id,
+                shape: geo::encode_path(&points),
+            };
+            let stored = db
+                .write(move |conn| {
+                    let exists = routes::table
+                        .filter(routes::routeid.eq(&shape.routeid))
+                        .filter(routes::deleted_at.is_null())
+                        .count()
+                        .get_result::<i64>(conn)?
+                        > 0;
+                    if !exists {
+                        return Ok(None);
+                    }
+                    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+                        let before: Option<String> = route_shapes::table
+                            .select(route_shapes::shape)
+                            .filter(route_shapes::routeid.eq(&shape.routeid))
+                            .first(conn)
+                            .optional()?;
+                        diesel::insert_into(route_shapes::table)
+                            .values(&shape)
+                            .on_conflict(route_shapes::routeid)
+                            .do_update()
+                            .set(&shape)
+                            .execute(conn)?;
+                        let action = if before.is_some() { "update" } else { "create" };
+                        Change::new(action, "route_shape", &shape.routeid)
+                            .before(&before)
+                            .after(&shape.shape)
+                            .record(conn, &actor)?;
+                        Ok(Some(()))
+                    })
+                })
+                .await?;
+            Ok(stored)
+        }
+        None => Err(BadRequest(Some("shape needs at least two valid points"))),
+    };
     let options = match core_options().to_cors() {
         Ok(a) => a,
         Err(a) => return Ok(Err(a)),
@@ -173,13 +698,93 @@ async fn bus_post<'r, 'o: 'r>(db: Db, post: Json<RoutesIn>) -> Result<impl Respo
     Ok(options.respond_owned(move |guard| guard.responder(out)))
 }
 
-#[get("/")]
-async fn list<'r, 'o: 'r>(db: Db) -> Result<impl Responder<'r, 'o>> {
-    let ids: Vec<String> = db
-        .run(move |conn| routes::table.select(routes::busid).load(conn))
+/// Serves the road geometry of a route as a GeoJSON `LineString`, or as an
+/// encoded polyline with `?format=polyline`.
+#[get("/<id>/shape?<format>")]
+async fn shape_get<'r, 'o: 'r>(
+    db: Db,
+    id: String,
+    format: Option<String>,
+) -> Result<impl Responder<'r, 'o>> {
+    let shape: Option<RouteShapes> = db
+        .run(move |conn| {
+            route_shapes::table
+                .filter(route_shapes::routeid.eq(id))
+                .first(conn)
+                .optional()
+        })
         .await?;
+    let out = shape.map(|shape| {
+        let points = geo::decode_path(&shape.shape);
+        match format.as_deref() {
+            Some("polyline") => Json(ShapeOut::Encoded(EncodedShape {
+                polyline: geo::encode_polyline(&points),
+            })),
+            _ => Json(ShapeOut::GeoJson(LineString::new(&points))),
+        }
+    });
+    let options = match core_options().to_cors() {
+        Ok(a) => a,
+        Err(a) => return Ok(Err(a)),
+    };
+    Ok(options.respond_owned(move |guard| guard.responder(out)))
+}
 
-    let out: Json<Vec<String>> = Json(ids);
+/// Creates or replaces the stop lists of a route, answering 201 or 200. Buses
+/// assigned to the route stay on it. Replacing a deleted route brings it back
+/// as new. Stops at places that do not exist are refused as by `POST`.
+#[put("/<id>", data = "<post>")]
+async fn put_one_bus<'r, 'o: 'r>(
+    _live: LiveEdit,
+    db: Db,
+    actor: Actor,
+    id: String,
+    post: Json<RouteBody>,
+) -> Result<impl Responder<'r, 'o>> {
+    let post_value = post.into_inner();
+    let route = Routes {
+        routeid: id,
+        placeid: post_value.placeid,
+        inbound: post_value.inbound,
+        deleted_at: None,
+    };
+    let out = db
+        .write(move |conn| {
+            conn.transaction::<_, diesel::result::Error, _>(|conn| {
+                if let Some(refused) = refuse_unknown_stops(conn, &route)? {
+                    return Ok(Err(refused));
+                }
+                let previous: Option<Routes> = routes::table
+                    .filter(routes::routeid.eq(&route.routeid))
+                    .filter(routes::deleted_at.is_null())
+                    .first(conn)
+                    .optional()?;
+                diesel::insert_into(routes::table)
+                    .values(&route)
+                    .on_conflict(routes::routeid)
+                    .do_update()
+                    .set(&route)
+                    .execute(conn)?;
+                let mut places = route.all_stops();
+                places.extend(previous.iter().flat_map(Routes::all_stops));
+                reconcile(conn, Some(&places), true)?;
+                let action = if previous.is_some() {
+                    "update"
+                } else {
+                    "create"
+                };
+                Change::new(action, "route", &route.routeid)
+                    .before(&previous)
+                    .after(&route)
+                    .record(conn, &actor)?;
+                let status = match previous {
+                    Some(_) => Status::Ok,
+                    None => Status::Created,
+                };
+                Ok(Ok(Custom(status, Json(route))))
+            })
+        })
+        .await?;
     let options = match core_options().to_cors() {
         Ok(a) => a,
         Err(a) => return Ok(Err(a)),
@@ -187,36 +792,194 @@ async fn list<'r, 'o: 'r>(db: Db) -> Result<impl Responder<'r, 'o>> {
     Ok(options.respond_owned(move |guard| guard.responder(out)))
 }
 
-#[get("/<id>")]
-async fn get_one_bus<'r, 'o: 'r>(db: Db, id: String) -> Result<impl Responder<'r, 'o>> {
-    let outs = db
-        .run(move |conn| {
-            let out: Routes = routes::table
-                .filter(routes::busid.eq(id))
-                .first(conn)
-                .unwrap();
-            let route_bus = out.placeid;
-            let a: Vec<String> = route_bus.split('|').map(|a| a.to_owned()).collect();
-            let mut a2: Vec<(String, f32, f32)> = vec![];
-            for i in a {
-                let out2: PlaceLocation = match place_location::table
-                    .filter(place_location::busid.eq(i))
+/// Updates the given fields of a route. A rename carries the route's shape,
+/// assignments and position history over to the new id. Stops at places that
+/// do not exist are refused as by `POST`.
+#[patch("/<id>", data = "<post>")]
+async fn patch_one_bus<'r, 'o: 'r>(
+    _live: LiveEdit,
+    db: Db,
+    actor: Actor,
+    id: String,
+    post: Json<RoutePatch>,
+) -> Result<impl Responder<'r, 'o>> {
+    let patch = post.into_inner();
+    let out = db
+        .write(move |conn| {
+            conn.transaction::<_, diesel::result::Error, _>(|conn| {
+                let previous: Option<Routes> = routes::table
+                    .filter(routes::routeid.eq(&id))
+                    .filter(routes::deleted_at.is_null())
                     .first(conn)
-                {
-                    Ok(a) => a,
-                    Err(_) => continue,
+                    .optional()?;
+                let previous = match previous {
+                    Some(previous) => previous,
+                    None => return Ok(Ok(None)),
                 };
-                a2.push((out2.busid, out2.latitude, out2.longitude));
-                // a2.push( (i.to_owned(), 0.0, 0.0) );
+                let mut route = previous.clone();
+                if let Some(routeid) = patch.routeid.filter(|routeid| *routeid != id) {
+                    let taken = routes::table
+                        .filter(routes::routeid.eq(&routeid))
+                        .count()
+                        .get_result::<i64>(conn)?
+                        > 0;
+                    if taken {
+                        return Ok(Err(Refused::Taken(Conflict(Some(
+                            "a route with that id already exists",
+                        )))));
+                    }
+                    diesel::update(routes::table)
+                        .filter(routes::routeid.eq(&id))
+                        .set(routes::routeid.eq(&routeid))
+                        .execute(conn)?;
+                    diesel::update(route_shapes::table)
+                        .filter(route_shapes::routeid.eq(&id))
+                        .set(route_shapes::routeid.eq(&routeid))
+                        .execute(conn)?;
+                    diesel::update(vehicle_assignments::table)
+                        .filter(vehicle_assignments::routeid.eq(&id))
+                        .set(vehicle_assignments::routeid.eq(&routeid))
+                        .execute(conn)?;
+                    diesel::update(location_history::table)
+                        .filter(location_history::routeid.eq(&id))
+                        .set(location_history::routeid.eq(&routeid))
+                        .execute(conn)?;
+                    route.routeid = routeid;
+                }
+                if let Some(placeid) = patch.placeid {
+                    route.placeid = placeid;
+                }
+                if let Some(inbound) = patch.inbound {
+                    route.inbound = (!inbound.is_empty()).then_some(inbound);
+                }
+                if let Some(refused) = refuse_unknown_stops(conn, &route)? {
+                    return Ok(Err(Refused::MissingPlaces(refused)));
+                }
+                diesel::insert_into(routes::table)
+                    .values(&route)
+                    .on_conflict(routes::routeid)
+                    .do_update()
+                    .set(&route)
+                    .execute(conn)?;
+                let mut places = route.all_stops();
+                places.extend(previous.all_stops());
+                reconcile(conn, Some(&places), true)?;
+                Change::new("update", "route", &id)
+                    .before(&previous)
+                    .after(&route)
+                    .record(conn, &actor)?;
+                Ok(Ok(Some(Json(route))))
+            })
+        })
+        .await?;
+    let options = match core_options().to_cors() {
+        Ok(a) => a,
+        Err(a) => return Ok(Err(a)),
+    };
+    Ok(options.respond_owned(move |guard| guard.responder(out)))
+}
+
+/// Puts a bus on this route, taking it off any route it ran before.
+#[post("/<id>/assign", data = "<post>")]
+async fn assign_post<'r, 'o: 'r>(
+    db: Db,
+    actor: Actor,
+    positions: &State<Positions>,
+    id: String,
+    post: Json<AssignIn>,
+) -> Result<impl Responder<'r, 'o>> {
+    let positions = positions.inner().clone();
+    let out = db
+        .write(move |conn| {
+            let out = conn.transaction::<_, diesel::result::Error, _>(|conn| {
+                let route: Option<Routes> = routes::table
+                    .filter(routes::routeid.eq(&id))
+                    .filter(routes::deleted_at.is_null())
+                    .first(conn)
+                    .optional()?;
+                match route {
+                    Some(route) => assign(conn, &post.busid, &route, &actor).map(Some),
+                    None => Ok(None),
+                }
+            })?;
+            if out.is_some() {
+                bus::track(conn, &positions, &post.busid)?;
             }
-            Routing {
-                busid: out.busid,
-                places: a2,
+            Ok::<_, diesel::result::Error>(out)
+        })
+        .await?;
+    let options = match core_options().to_cors() {
+        Ok(a) => a,
+        Err(a) => return Ok(Err(a)),
+    };
+    Ok(options.respond_owned(move |guard| guard.responder(out)))
+}
+
+/// Replaces bus `from` on this route with bus `to`.
+#[post("/<id>/swap", data = "<post>")]
+async fn swap_post<'r, 'o: 'r>(
+    db: Db,
+    actor: Actor,
+    positions: &State<Positions>,
+    id: String,
+    post: Json<SwapIn>,
+) -> Result<impl Responder<'r, 'o>> {
+    let positions = positions.inner().clone();
+    let out = db
+        .write(move |conn| {
+            let out = conn.transaction::<_, diesel::result::Error, _>(|conn| {
+                let route: Option<Routes> = routes::table
+                    .filter(routes::routeid.eq(&id))
+                    .filter(routes::deleted_at.is_null())
+                    .first(conn)
+                    .optional()?;
+                let route = match route {
+                    Some(route) => route,
+                    None => return Ok(None),
+                };
+                if !unassign(conn, &post.from, &route, &actor)? {
+                    return Ok(None);
+                }
+                assign(conn, &post.to, &route, &actor).map(Some)
+            })?;
+            if out.is_some() {
+                bus::track(conn, &positions, &post.to)?;
             }
+            Ok::<_, diesel::result::Error>(out)
         })
-        .await;
+        .await?;
+    let options = match core_options().to_cors() {
+        Ok(a) => a,
+        Err(a) => return Ok(Err(a)),
+    };
+    Ok(options.respond_owned(move |guard| guard.responder(out)))
+}
+
+/// Takes a bus off this route.
+#[delete("/<id>/assign/<busid>")]
+async fn unassign_one_bus<'r, 'o: 'r>(
+    db: Db,
+    actor: Actor,
+    id: String,
+    busid: String,
+) -> Result<impl Responder<'r, 'o>> {
+    let out = db
+        .write(move |conn| {
+            conn.transaction::<_, diesel::result::Error, _>(|conn| {
+                let route: Option<Routes> = routes::table
+                    .filter(routes::routeid.eq(&id))
+                    .filter(routes::deleted_at.is_null())
+                    .first(conn)
+                    .optional()?;
+                match route {
+                    Some(route) => unassign(conn, &busid, &route, &actor),
+                    None => Ok(false),
+                }
+            })
+        })
+        .await?;
 
-    let out = Json(outs);
+    let out = out.then_some(());
     let options = match core_options().to_cors() {
         Ok(a) => a,
         Err(a) => return Ok(Err(a)),
@@ -224,13 +987,379 @@ async fn get_one_bus<'r, 'o: 'r>(db: Db, id: String) -> Result<impl Responder<'r
     Ok(options.respond_owned(move |guard| guard.responder(out)))
 }
 
-#[delete("/<id>")]
-async fn delete_one_bus<'r, 'o: 'r>(db: Db, id: String) -> Result<impl Responder<'r, 'o>> {
+/// Assignments of this route, active ones only unless `?all=true`.
+#[get("/<id>/buses?<all>")]
+async fn list_buses<'r, 'o: 'r>(
+    db: Db,
+    id: String,
+    all: Option<bool>,
+) -> Result<impl Responder<'r, 'o>> {
+    let out: Vec<VehicleAssignments> = db
+        .run(move |conn| {
+            let mut query = vehicle_assignments::table
+                .select((
+                    vehicle_assignments::busid,
+                    vehicle_assignments::routeid,
+                    vehicle_assignments::valid_from,
+                    vehicle_assignments::valid_to,
+                ))
+                .filter(vehicle_assignments::routeid.eq(id))
+                .order(vehicle_assignments::valid_from.desc())
+                .into_boxed();
+            if !all.unwrap_or(false) {
+                query = query.filter(vehicle_assignments::valid_to.is_null());
+            }
+            query.load(conn)
+        })
+        .await?;
+    let options = match core_options().to_cors() {
+        Ok(a) => a,
+        Err(a) => return Ok(Err(a)),
+    };
+    Ok(options.respond_owned(move |guard| guard.responder(Json(out))))
+}
+
+/// Reports places whose `busses` entry drifted from the route assignments.
+#[get("/consistency")]
+async fn consistency<'r, 'o: 'r>(db: Db) -> Result<impl Responder<'r, 'o>> {
+    let out = db.run(move |conn| reconcile(conn, None, false)).await?;
+    let options = match core_options().to_cors() {
+        Ok(a) => a,
+        Err(a) => return Ok(Err(a)),
+    };
+    Ok(options.respond_owned(move |guard| guard.responder(Json(out))))
+}
+
+/// Rewrites drifted `busses` entries to match the route assignments and
+/// reports what was changed.
+#[post("/consistency/repair")]
+async fn consistency_repair<'r, 'o: 'r>(db: Db, actor: Actor) -> Result<impl Responder<'r, 'o>> {
+    let out = db
+        .write(move |conn| {
+            conn.transaction::<_, diesel::result::Error, _>(|conn| {
+                let drift = reconcile(conn, None, true)?;
+                if !drift.is_empty() {
+                    Change::new("repair", "busses", "*")
+                        .before(&drift)
+                        .record(conn, &actor)?;
+                }
+                Ok(drift)
+            })
+        })
+        .await?;
+    let options = match core_options().to_cors() {
+        Ok(a) => a,
+        Err(a) => return Ok(Err(a)),
+    };
+    Ok(options.respond_owned(move |guard| guard.responder(Json(out))))
+}
+
+/// A route whose stop lists name places that do not exist.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct MissingPlaces {
+    routeid: String,
+    missing: Vec<String>,
+}
+
+/// Stops of `route` missing from `place_location` or deleted, each once.
+fn unknown_stops(conn: &mut db::Connection, route: &Routes) -> QueryResult<Vec<String>> {
+    let stops: BTreeSet<String> = route.all_stops().into_iter().collect();
+    let known: BTreeSet<String> = place_location::table
+        .select(place_location::busid)
+        .filter(place_location::busid.eq_any(&stops))
+        .filter(place_location::deleted_at.is_null())
+        .load::<String>(conn)?
+        .into_iter()
+        .collect();
+    Ok(stops.difference(&known).cloned().collect())
+}
+
+/// A 422 naming the stops of `route` missing from `place_location` or
+/// deleted, or `None` when every stop is there. Call it in the transaction
+/// that writes the route, so a place deleted meanwhile is not missed.
+fn refuse_unknown_stops(
+    conn: &mut db::Connection,
+    route: &Routes,
+) -> QueryResult<Option<Custom<Json<MissingPlaces>>>> {
+    let missing = unknown_stops(conn, route)?;
+    Ok((!missing.is_empty()).then(|| {
+        Custom(
+            Status::UnprocessableEntity,
+            Json(MissingPlaces {
+                routeid: route.routeid.clone(),
+                missing,
+            }),
+        )
+    }))
+}
+
+/// Why `PATCH /routes/<id>` left a route as it was.
+#[derive(Responder)]
+enum Refused {
+    /// The new id belongs to another route.
+    Taken(Conflict<Option<&'static str>>),
+    /// The new stop lists name places that do not exist.
+    MissingPlaces(Custom<Json<MissingPlaces>>),
+}
+
+/// Lists routes stopping at places missing from `place_location` or deleted.
+/// `/routes/<id>` lists such stops under `missing` instead of placing them.
+#[get("/missing_places")]
+async fn missing_places<'r, 'o: 'r>(db: Db) -> Result<impl Responder<'r, 'o>> {
     let out = db
         .run(move |conn| {
-            diesel::delete(routes::table)
-                .filter(routes::busid.eq(id))
-                .execute(conn)
+            let known: BTreeSet<String> = place_location::table
+                .select(place_location::busid)
+                .filter(place_location::deleted_at.is_null())
+                .load::<String>(conn)?
+                .into_iter()
+                .collect();
+            let routes: Vec<Routes> = routes::table
+                .filter(routes::deleted_at.is_null())
+                .load(conn)?;
+            let out: Vec<MissingPlaces> = routes
+                .into_iter()
+                .filter_map(|route| {
+                    let missing: Vec<String> = route
+                        .all_stops()
+                        .into_iter()
+                        .filter(|stop| !known.contains(stop))
+                        .collect::<BTreeSet<_>>()
+                        .into_iter()
+                        .collect();
+                    (!missing.is_empty()).then_some(MissingPlaces {
+                        routeid: route.routeid,
+                        missing,
+                    })
+                })
+                .collect();
+            Ok::<_, diesel::result::Error>(out)
+        })
+        .await?;
+    let options = match core_options().to_cors() {
+        Ok(a) => a,
+        Err(a) => return Ok(Err(a)),
+    };
+    Ok(options.respond_owned(move |guard| guard.responder(Json(out))))
+}
+
+/// Route ids, optionally only those stopping at `?place=` or run by `?bus=`,
+/// sorted by `?sort=routeid` or `?sort=-routeid` and paged with `?limit=` and
+/// `?offset=`. Deleted routes are listed too with `?include_deleted=true`
+/// from the admin.
+#[get("/?<place>&<bus>&<sort>&<paging..>")]
+async fn list<'r, 'o: 'r>(
+    db: Db,
+    place: Option<String>,
+    bus: Option<String>,
+    sort: Option<Sort>,
+    include_deleted: IncludeDeleted,
+    paging: Paging,
+) -> Result<impl Responder<'r, 'o>> {
+    let out = match sort {
+        Some(sort) if sort.field != "routeid" => Err(BadRequest(Some("unknown sort field"))),
+        sort => {
+            let descending = sort.is_some_and(|sort| sort.descending);
+            let ids: Vec<String> = db
+                .run(move |conn| {
+                    let mut query = routes::table.select(routes::routeid).into_boxed();
+                    if !include_deleted.0 {
+                        query = query.filter(routes::deleted_at.is_null());
+                    }
+                    if let Some(place) = place {
+                        query = query.filter(
+                            sql::<Bool>("(instr('|' || placeid || '|', '|' || ")
+                                .bind::<Text, _>(place.clone())
+                                .sql(" || '|') > 0 OR instr('|' || COALESCE(inbound, '') || '|', '|' || ")
+                                .bind::<Text, _>(place)
+                                .sql(" || '|') > 0)"),
+                        );
+                    }
+                    if let Some(bus) = bus {
+                        query = query.filter(
+                            routes::routeid.eq_any(
+                                vehicle_assignments::table
+                                    .select(vehicle_assignments::routeid)
+                                    .filter(vehicle_assignments::busid.eq(bus))
+                                    .filter(vehicle_assignments::valid_to.is_null()),
+                            ),
+                        );
+                    }
+                    query = if descending {
+                        query.order(routes::routeid.desc())
+                    } else {
+                        query.order(routes::routeid.asc())
+                    };
+                    query
+                        .limit(paging.fetch())
+                        .offset(paging.offset())
+                        .load(conn)
+                })
+                .await?;
+            Ok(paging.page(ids))
+        }
+    };
+    let options = match core_options().to_cors() {
+        Ok(a) => a,
+        Err(a) => return Ok(Err(a)),
+    };
+    Ok(options.respond_owned(move |guard| guard.responder(out)))
+}
+
+/// A route with its stops in running order. Without `?direction=`, the stops
+/// follow the direction bus `?busid=` (or else the first bus on the route) is
+/// currently running in.
+#[get("/<id>?<direction>&<busid>")]
+async fn get_one_bus<'r, 'o: 'r>(
+    db: Db,
+    cache: &State<ResponseCache>,
+    positions: &State<Positions>,
+    conditional: Conditional,
+    id: String,
+    direction: Option<Direction>,
+    busid: Option<String>,
+    languages: Languages,
+) -> Result<impl Responder<'r, 'o>> {
+    let version = cache.version();
+    let positions = positions.inner().clone();
+    // The direction follows the bus, which moves without bumping the data
+    // version, so it is worked out first and becomes part of the cache key.
+    let (id, buses, direction) = db
+        .run(move |conn| {
+            let buses = active_buses(conn, &id)?;
+            let direction = match (direction, busid.or_else(|| buses.first().cloned())) {
+                (Some(direction), _) => direction,
+                (None, Some(busid)) => bus::direction(conn, &positions, &busid)?
+                    .as_deref()
+                    .and_then(Direction::parse)
+                    .unwrap_or(Direction::Outbound),
+                (None, None) => Direction::Outbound,
+            };
+            Ok::<_, diesel::result::Error>((id, buses, direction))
+        })
+        .await?;
+    let conditional = conditional.vary(direction.as_str());
+    let entry = match cache.get(&conditional) {
+        Some(entry) => Some(entry),
+        None => {
+            let routing = db
+                .run(move |conn| build_routing(conn, &id, buses, direction, &languages))
+                .await?;
+            routing.map(|routing| cache.put(&conditional, version, &routing, None))
+        }
+    };
+
+    let out = entry.map(|entry| conditional.respond(entry));
+    let options = match core_options().to_cors() {
+        Ok(a) => a,
+        Err(a) => return Ok(Err(a)),
+    };
+    Ok(options.respond_owned(move |guard| guard.responder(out)))
+}
+
+/// The body of `/routes/<id>` for buses running `direction`, or `None` when
+/// there is no such route or it is deleted.
+fn build_routing(
+    conn: &mut db::Connection,
+    id: &str,
+    buses: Vec<String>,
+    direction: Direction,
+    languages: &Languages,
+) -> QueryResult<Option<Routing>> {
+    let out: Option<Routes> = routes::table
+        .filter(routes::routeid.eq(id))
+        .filter(routes::deleted_at.is_null())
+        .first(conn)
+        .optional()?;
+    let out = match out {
+        Some(out) => out,
+        None => return Ok(None),
+    };
+    let a: Vec<String> = out.stops(direction);
+    let names = locale::localize(conn, languages, &a)?;
+    let located: BTreeMap<String, PlaceLocation> = place_location::table
+        .filter(place_location::busid.eq_any(&a))
+        .filter(place_location::deleted_at.is_null())
+        .load::<PlaceLocation>(conn)?
+        .into_iter()
+        .map(|place| (place.busid.clone(), place))
+        .collect();
+    let mut a2: Vec<(String, f32, f32, String)> = vec![];
+    let mut missing = vec![];
+    for i in a {
+        let out2 = match located.get(&i) {
+            Some(a) => a,
+            None => {
+                missing.push(i);
+                continue;
+            }
+        };
+        let name = names.get(&i).unwrap_or(&i).clone();
+        a2.push((i, out2.latitude, out2.longitude, name));
+    }
+    let shape: Option<String> = route_shapes::table
+        .select(route_shapes::shape)
+        .filter(route_shapes::routeid.eq(&out.routeid))
+        .first(conn)
+        .optional()?;
+    let shape = shape.as_deref().map(geo::decode_path).map(|mut shape| {
+        if direction == Direction::Inbound {
+            shape.reverse();
+        }
+        shape
+    });
+    Ok(Some(Routing {
+        routeid: out.routeid,
+        buses,
+        direction,
+        places: a2,
+        missing,
+        shape,
+    }))
+}
+
+/// Deletes a route and takes its buses off it. The route and its shape can be
+/// restored until the retention window in `[soft_delete]` runs out.
+#[delete("/<id>")]
+async fn delete_one_bus<'r, 'o: 'r>(
+    _live: LiveEdit,
+    db: Db,
+    actor: Actor,
+    id: String,
+) -> Result<impl Responder<'r, 'o>> {
+    let out = db
+        .write(move |conn| {
+            conn.transaction::<_, diesel::result::Error, _>(|conn| {
+                let route: Option<Routes> = routes::table
+                    .filter(routes::routeid.eq(&id))
+                    .filter(routes::deleted_at.is_null())
+                    .first(conn)
+                    .optional()?;
+                let route = match route {
+                    Some(route) => route,
+                    None => return Ok(0),
+                };
+                let at = now();
+                diesel::update(vehicle_assignments::table)
+                    .filter(vehicle_assignments::routeid.eq(&id))
+                    .filter(vehicle_assignments::valid_to.is_null())
+                    .set(vehicle_assignments::valid_to.eq(at))
+                    .execute(conn)?;
+                let deleted = diesel::update(routes::table)
+                    .filter(routes::routeid.eq(&id))
+                    .set(routes::deleted_at.eq(at))
+                    .execute(conn)?;
+                reconcile(conn, Some(&route.all_stops()), true)?;
+                Change::new("delete", "route", &id)
+                    .before(&route)
+                    .after(&Routes {
+                        deleted_at: Some(at),
+                        ..route.clone()
+                    })
+                    .record(conn, &actor)?;
+                Ok(deleted)
+            })
         })
         .await?;
 
@@ -242,16 +1371,103 @@ async fn delete_one_bus<'r, 'o: 'r>(db: Db, id: String) -> Result<impl Responder
     Ok(options.respond_owned(move |guard| guard.responder(out)))
 }
 
-async fn run_migrations(rocket: Rocket<Build>) -> Rocket<Build> {
-    use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+/// Undoes the delete of a route, shape included. Buses that ran it stay off
+/// it until they are assigned again.
+#[post("/<id>/restore")]
+async fn restore_one_bus<'r, 'o: 'r>(
+    _live: LiveEdit,
+    db: Db,
+    actor: Actor,
+    id: String,
+) -> Result<impl Responder<'r, 'o>> {
+    let out = db
+        .write(move |conn| {
+            conn.transaction::<_, diesel::result::Error, _>(|conn| {
+                let before: Option<Routes> = routes::table
+                    .filter(routes::routeid.eq(&id))
+                    .filter(routes::deleted_at.is_not_null())
+                    .first(conn)
+                    .optional()?;
+                let before = match before {
+                    Some(before) => before,
+                    None => return Ok(None),
+                };
+                let route = Routes {
+                    deleted_at: None,
+                    ..before.clone()
+                };
+                diesel::update(routes::table)
+                    .filter(routes::routeid.eq(&id))
+                    .set(routes::deleted_at.eq(route.deleted_at))
+                    .execute(conn)?;
+                Change::new("restore", "route", &id)
+                    .before(&before)
+                    .after(&route)
+                    .record(conn, &actor)?;
+                Ok(Some(Json(route)))
+            })
+        })
+        .await?;
+    let options = match core_options().to_cors() {
+        Ok(a) => a,
+        Err(a) => return Ok(Err(a)),
+    };
+    Ok(options.respond_owned(move |guard| guard.responder(out)))
+}
+
+/// Removes routes deleted at or before `cutoff` for good, along with their
+/// shapes, and returns how many went. Their closed assignments stay, so past
+/// trips keep their route.
+fn purge(conn: &mut db::Connection, cutoff: i64) -> QueryResult<usize> {
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        let expired: Vec<Routes> = routes::table
+            .filter(routes::deleted_at.le(cutoff))
+            .load(conn)?;
+        for route in &expired {
+            diesel::delete(route_shapes::table)
+                .filter(route_shapes::routeid.eq(&route.routeid))
+                .execute(conn)?;
+            diesel::delete(routes::table)
+                .filter(routes::routeid.eq(&route.routeid))
+                .execute(conn)?;
+            Change::new("purge", "route", &route.routeid)
+                .before(route)
+                .record(conn, &Actor::system())?;
+        }
+        Ok(expired.len())
+    })
+}
+
+/// Purges expired deletes every `purge_interval` seconds.
+async fn start_purging(rocket: &Rocket<Orbit>, config: RetentionConfig) {
+    let cache = rocket
+        .state::<ResponseCache>()
+        .expect("response cache")
+        .clone();
+    let db = Db::get_one(rocket).await.expect("database connection");
+    rocket::tokio::spawn(async move {
+        let mut interval = rocket::tokio::time::interval(config.period());
+        loop {
+            interval.tick().await;
+            let cutoff = config.cutoff();
+            match db.write(move |conn| purge(conn, cutoff)).await {
+                Ok(0) => {}
+                // Listings with `?include_deleted=true` showed them.
+                Ok(_) => cache.bump(),
+                Err(e) => error!("purging deleted routes: {}", e),
+            }
+        }
+    });
+}
 
-    const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
+async fn run_migrations(rocket: Rocket<Build>) -> Rocket<Build> {
+    use diesel_migrations::MigrationHarness;
 
     Db::get_one(&rocket)
         .await
         .expect("database connection")
         .run(|conn| {
-            conn.run_pending_migrations(MIGRATIONS)
+            conn.run_pending_migrations(db::MIGRATIONS)
                 .expect("diesel migrations");
         })
         .await;
@@ -260,13 +1476,112 @@ async fn run_migrations(rocket: Rocket<Build>) -> Rocket<Build> {
 }
 
 pub fn route_data() -> AdHoc {
-    AdHoc::on_ignite("Data related to routes", |rocket| async {
-        rocket
-            .attach(Db::fairing())
+    AdHoc::try_on_ignite("Data related to routes", |rocket| async {
+        let config: RetentionConfig = match config::read(&rocket, "soft_delete") {
+            Some(config) => config,
+            None => return Err(rocket),
+        };
+        Ok(rocket
             .attach(AdHoc::on_ignite("Diesel Migrations", run_migrations))
+            .attach(AdHoc::on_liftoff("Purge deleted routes", move |rocket| {
+                Box::pin(start_purging(rocket, config))
+            }))
             .mount(
                 "/routes",
-                routes![bus_post, list, get_one_bus, delete_one_bus],
-            )
+                routes![
+                    bus_post,
+                    put_one_bus,
+                    patch_one_bus,
+                    shape_post,
+                    shape_get,
+                    assign_post,
+                    swap_post,
+                    unassign_one_bus,
+                    list_buses,
+                    consistency,
+                    consistency_repair,
+                    missing_places,
+                    list,
+                    get_one_bus,
+                    delete_one_bus,
+                    restore_one_bus
+                ],
+            ))
     })
 }
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use super::diesel::connection::SimpleConnection;
+    use super::*;
+
+    fn places() -> db::Connection {
+        let mut conn = db::Connection::establish(":memory:").unwrap();
+        conn.batch_execute(
+            "CREATE TABLE place_location (
+                busid TEXT NOT NULL PRIMARY KEY,
+                latitude FLOAT NOT NULL,
+                longitude FLOAT NOT NULL,
+                deleted_at BIGINT
+            );
+            INSERT INTO place_location VALUES ('p1', 31.1, 77.2, NULL);
+            INSERT INTO place_location VALUES ('p2', 31.2, 77.1, NULL);
+            INSERT INTO place_location VALUES ('gone', 31.3, 77.0, 1700000000);",
+        )
+        .unwrap();
+        conn
+    }
+
+    fn route(placeid: &str, inbound: Option<&str>) -> Routes {
+        Routes {
+            routeid: "r1".to_owned(),
+            placeid: placeid.to_owned(),
+            inbound: inbound.map(str::to_owned),
+            deleted_at: None,
+        }
+    }
+
+    #[test]
+    fn known_stops_pass() {
+        let mut conn = places();
+        assert!(unknown_stops(&mut conn, &route("p1|p2", None))
+            .unwrap()
+            .is_empty());
+        assert!(
+            refuse_unknown_stops(&mut conn, &route("p1|p2", Some("p2|p1")))
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn missing_and_deleted_stops_are_named_once() {
+        let mut conn = places();
+        assert_eq!(
+            unknown_stops(
+                &mut conn,
+                &route("p1|nowhere|gone", Some("gone|p2|nowhere"))
+            )
+            .unwrap(),
+            ["gone", "nowhere"]
+        );
+    }
+
+    #[test]
+    fn inbound_stops_are_checked_too() {
+        let mut conn = places();
+        let refused = refuse_unknown_stops(&mut conn, &route("p1|p2", Some("p2|nowhere")))
+            .unwrap()
+            .unwrap();
+        assert_eq!(refused.0, Status::UnprocessableEntity);
+        assert_eq!(refused.1.routeid, "r1");
+        assert_eq!(refused.1.missing, ["nowhere"]);
+    }
+
+    #[test]
+    fn inbound_defaults_to_the_outbound_stops_reversed() {
+        let route = route("p1|p2|p3", None);
+        assert_eq!(route.stops(Direction::Inbound), ["p3", "p2", "p1"]);
+        assert_eq!(route.all_stops(), ["p1", "p2", "p3", "p3", "p2", "p1"]);
+    }
+}
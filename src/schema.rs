@@ -1,5 +1,18 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    audit_log (id) {
+        id -> Integer,
+        at -> BigInt,
+        actor -> Text,
+        action -> Text,
+        entity -> Text,
+        entity_id -> Text,
+        before -> Nullable<Text>,
+        after -> Nullable<Text>,
+    }
+}
+
 diesel::table! {
     busses (placeid) {
         placeid -> Text,
@@ -12,6 +25,44 @@ diesel::table! {
         busid -> Text,
         latitude -> Float,
         longitude -> Float,
+        recorded_at -> BigInt,
+        speed -> Nullable<Float>,
+        heading -> Nullable<Float>,
+        accuracy -> Nullable<Float>,
+        altitude -> Nullable<Float>,
+        odometer -> Nullable<Double>,
+        snapped_latitude -> Nullable<Float>,
+        snapped_longitude -> Nullable<Float>,
+        distance_along -> Nullable<Float>,
+        direction -> Nullable<Text>,
+        estimated_accuracy -> Nullable<Float>,
+    }
+}
+
+diesel::table! {
+    location_history (id) {
+        id -> Integer,
+        busid -> Text,
+        latitude -> Float,
+        longitude -> Float,
+        recorded_at -> BigInt,
+        speed -> Nullable<Float>,
+        heading -> Nullable<Float>,
+        accuracy -> Nullable<Float>,
+        altitude -> Nullable<Float>,
+        odometer -> Nullable<Double>,
+        snapped_latitude -> Nullable<Float>,
+        snapped_longitude -> Nullable<Float>,
+        distance_along -> Nullable<Float>,
+        direction -> Nullable<Text>,
+        routeid -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    place_aliases (alias) {
+        alias -> Text,
+        busid -> Text,
     }
 }
 
@@ -20,19 +71,101 @@ diesel::table! {
         busid -> Text,
         latitude -> Float,
         longitude -> Float,
+        name -> Text,
+        deleted_at -> Nullable<BigInt>,
+    }
+}
+
+diesel::table! {
+    place_names (busid, lang) {
+        busid -> Text,
+        lang -> Text,
+        name -> Text,
+    }
+}
+
+diesel::table! {
+    rejected_locations (id) {
+        id -> Integer,
+        busid -> Text,
+        latitude -> Float,
+        longitude -> Float,
+        recorded_at -> BigInt,
+        accuracy -> Nullable<Float>,
+        reason -> Text,
+        received_at -> BigInt,
     }
 }
 
 diesel::table! {
-    routes (busid) {
+    revision_places (revision, busid) {
+        revision -> Integer,
         busid -> Text,
+        latitude -> Float,
+        longitude -> Float,
+        name -> Text,
+    }
+}
+
+diesel::table! {
+    revision_routes (revision, routeid) {
+        revision -> Integer,
+        routeid -> Text,
+        placeid -> Text,
+        inbound -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    revisions (id) {
+        id -> Integer,
+        state -> Text,
+        note -> Text,
+        created_at -> BigInt,
+        published_at -> Nullable<BigInt>,
+        base -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    route_shapes (routeid) {
+        routeid -> Text,
+        shape -> Text,
+    }
+}
+
+diesel::table! {
+    routes (routeid) {
+        routeid -> Text,
         placeid -> Text,
+        inbound -> Nullable<Text>,
+        deleted_at -> Nullable<BigInt>,
+    }
+}
+
+diesel::table! {
+    vehicle_assignments (id) {
+        id -> Integer,
+        busid -> Text,
+        routeid -> Text,
+        valid_from -> BigInt,
+        valid_to -> Nullable<BigInt>,
     }
 }
 
 diesel::allow_tables_to_appear_in_same_query!(
+    audit_log,
     busses,
     current_location,
+    location_history,
+    place_aliases,
     place_location,
+    place_names,
+    rejected_locations,
+    revision_places,
+    revision_routes,
+    revisions,
+    route_shapes,
     routes,
+    vehicle_assignments,
 );
@@ -0,0 +1,318 @@
+use rocket::fairing::AdHoc;
+use rocket::form::{self, FromFormField, ValueField};
+use rocket::response::{Debug, Responder};
+use rocket::serde::{json::Json, Serialize};
+use rocket::{http, Build, Rocket};
+
+#[cfg(feature = "postgres")]
+use self::diesel::dsl::sql;
+use self::diesel::prelude::*;
+#[cfg(feature = "postgres")]
+use self::diesel::sql_types::{Bool, Float};
+use rocket_cors::{AllowedHeaders, AllowedOrigins, CorsOptions, Method};
+use rocket_sync_db_pools::diesel;
+
+use crate::db::{self, Db};
+use crate::geo;
+use crate::paging::Bbox;
+
+type Result<T, E = Debug<diesel::result::Error>> = std::result::Result<T, E>;
+
+fn core_options() -> CorsOptions {
+    rocket_cors::CorsOptions {
+        allowed_origins: AllowedOrigins::all(),
+        allowed_methods: vec![http::Method::Get, http::Method::Options]
+            .into_iter()
+            .map(Method)
+            .collect(),
+        allowed_headers: AllowedHeaders::all(),
+        allow_credentials: true,
+        fairing_route_base: "/".to_owned(),
+        max_age: Some(42),
+        ..Default::default()
+    }
+}
+
+table! {
+    current_location (busid) {
+        busid -> Text,
+        latitude -> Float,
+        longitude -> Float,
+        recorded_at -> BigInt,
+        heading -> Nullable<Float>,
+        direction -> Nullable<Text>,
+    }
+}
+
+table! {
+    place_location (busid) {
+        busid -> Text,
+        latitude -> Float,
+        longitude -> Float,
+        name -> Text,
+        deleted_at -> Nullable<BigInt>,
+    }
+}
+
+table! {
+    route_shapes (routeid) {
+        routeid -> Text,
+        shape -> Text,
+    }
+}
+
+table! {
+    routes (routeid) {
+        routeid -> Text,
+        deleted_at -> Nullable<BigInt>,
+    }
+}
+
+allow_tables_to_appear_in_same_query!(route_shapes, routes);
+
+/// A bus marker on the map.
+#[derive(Debug, Clone, Serialize, Queryable)]
+#[serde(crate = "rocket::serde")]
+struct Bus {
+    busid: String,
+    latitude: f32,
+    longitude: f32,
+    recorded_at: i64,
+    heading: Option<f32>,
+    direction: Option<String>,
+}
+
+/// A stop marker on the map.
+#[derive(Debug, Clone, Serialize, Queryable)]
+#[serde(crate = "rocket::serde")]
+struct Stop {
+    busid: String,
+    latitude: f32,
+    longitude: f32,
+    name: String,
+}
+
+/// A route drawn on the map, as a GeoJSON `LineString`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(crate = "rocket::serde")]
+struct RouteLine {
+    routeid: String,
+    #[serde(rename = "type")]
+    kind: &'static str,
+    coordinates: Vec<[f32; 2]>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(crate = "rocket::serde")]
+struct Viewport {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    buses: Option<Vec<Bus>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stops: Option<Vec<Stop>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    routes: Option<Vec<RouteLine>>,
+}
+
+/// `?layers=buses,stops,routes`; any subset, all three when left out.
+#[derive(Debug, Clone, Copy)]
+struct Layers {
+    buses: bool,
+    stops: bool,
+    routes: bool,
+}
+
+impl Default for Layers {
+    fn default() -> Self {
+        Layers {
+            buses: true,
+            stops: true,
+            routes: true,
+        }
+    }
+}
+
+impl<'v> FromFormField<'v> for Layers {
+    fn from_value(field: ValueField<'v>) -> form::Result<'v, Self> {
+        let mut layers = Layers {
+            buses: false,
+            stops: false,
+            routes: false,
+        };
+        for layer in field.value.split(',').map(str::trim) {
+            match layer {
+                "buses" => layers.buses = true,
+                "stops" => layers.stops = true,
+                "routes" => layers.routes = true,
+                _ => {
+                    return Err(
+                        form::Error::validation("layers are buses, stops and routes").into(),
+                    )
+                }
+            }
+        }
+        Ok(layers)
+    }
+}
+
+/// Zoom level at and above which route shapes are served at full detail.
+const FULL_DETAIL_ZOOM: u8 = 16;
+
+/// Whether any segment of `points` may cross `bbox`, judged by the bounding
+/// box of each segment.
+fn crosses(points: &[(f32, f32)], bbox: &Bbox) -> bool {
+    let inside = |(lat, lon): (f32, f32)| {
+        (bbox.min_lat..=bbox.max_lat).contains(&lat) && (bbox.min_lon..=bbox.max_lon).contains(&lon)
+    };
+    match points {
+        [point] => inside(*point),
+        _ => points.windows(2).any(|pair| {
+            let (a, b) = (pair[0], pair[1]);
+            a.0.min(b.0) <= bbox.max_lat
+                && a.0.max(b.0) >= bbox.min_lat
+                && a.1.min(b.1) <= bbox.max_lon
+                && a.1.max(b.1) >= bbox.min_lon
+        }),
+    }
+}
+
+/// `geom && ST_MakeEnvelope(...)` for the PostGIS point column that
+/// `place_location` and `current_location` carry on PostgreSQL, answered from
+/// its GiST index.
+#[cfg(feature = "postgres")]
+fn in_envelope<QS>(bbox: &Bbox) -> Box<dyn BoxableExpression<QS, diesel::pg::Pg, SqlType = Bool>> {
+    Box::new(
+        sql::<Bool>("geom && ST_MakeEnvelope(")
+            .bind::<Float, _>(bbox.min_lon)
+            .sql(", ")
+            .bind::<Float, _>(bbox.min_lat)
+            .sql(", ")
+            .bind::<Float, _>(bbox.max_lon)
+            .sql(", ")
+            .bind::<Float, _>(bbox.max_lat)
+            .sql(", 4326)"),
+    )
+}
+
+/// Buses, stops and route shapes within `?bbox=`. Below `FULL_DETAIL_ZOOM`,
+/// `?zoom=` simplifies route shapes to about one pixel at that zoom level.
+/// Buses are read from `current_location`, so with batched live positions
+/// they may be up to one flush interval behind `/bus/all`.
+#[get("/?<bbox>&<layers>&<zoom>")]
+async fn viewport<'r, 'o: 'r>(
+    db: Db,
+    bbox: Bbox,
+    layers: Option<Layers>,
+    zoom: Option<u8>,
+) -> Result<impl Responder<'r, 'o>> {
+    let layers = layers.unwrap_or_default();
+    let out = db
+        .run(move |conn| -> QueryResult<Viewport> {
+            let buses = if layers.buses {
+                let query = current_location::table
+                    .order(current_location::busid)
+                    .into_boxed();
+                #[cfg(feature = "postgres")]
+                let query = query.filter(in_envelope(&bbox));
+                #[cfg(not(feature = "postgres"))]
+                let query = query
+                    .filter(current_location::latitude.between(bbox.min_lat, bbox.max_lat))
+                    .filter(current_location::longitude.between(bbox.min_lon, bbox.max_lon));
+                Some(query.load::<Bus>(conn)?)
+            } else {
+                None
+            };
+            let stops = if layers.stops {
+                let query = place_location::table
+                    .select((
+                        place_location::busid,
+                        place_location::latitude,
+                        place_location::longitude,
+                        place_location::name,
+                    ))
+                    .filter(place_location::deleted_at.is_null())
+                    .order(place_location::busid)
+                    .into_boxed();
+                #[cfg(feature = "postgres")]
+                let query = query.filter(in_envelope(&bbox));
+                #[cfg(not(feature = "postgres"))]
+                let query = query
+                    .filter(place_location::latitude.between(bbox.min_lat, bbox.max_lat))
+                    .filter(place_location::longitude.between(bbox.min_lon, bbox.max_lon));
+                Some(query.load::<Stop>(conn)?)
+            } else {
+                None
+            };
+            let routes = if layers.routes {
+                let shapes: Vec<(String, String)> = route_shapes::table
+                    .filter(
+                        route_shapes::routeid.eq_any(
+                            routes::table
+                                .select(routes::routeid)
+                                .filter(routes::deleted_at.is_null()),
+                        ),
+                    )
+                    .order(route_shapes::routeid)
+                    .load(conn)?;
+                let tolerance = zoom
+                    .filter(|zoom| *zoom < FULL_DETAIL_ZOOM)
+                    .map(|zoom| geo::meters_per_pixel(zoom, (bbox.min_lat + bbox.max_lat) / 2.0));
+                let lines = shapes
+                    .into_iter()
+                    .filter_map(|(routeid, shape)| {
+                        let points = geo::decode_path(&shape);
+                        if !crosses(&points, &bbox) {
+                            return None;
+                        }
+                        let points = match tolerance {
+                            Some(tolerance) => geo::simplify(&points, tolerance),
+                            None => points,
+                        };
+                        Some(RouteLine {
+                            routeid,
+                            kind: "LineString",
+                            coordinates: points.iter().map(|(lat, lon)| [*lon, *lat]).collect(),
+                        })
+                    })
+                    .collect();
+                Some(lines)
+            } else {
+                None
+            };
+            Ok(Viewport {
+                buses,
+                stops,
+                routes,
+            })
+        })
+        .await
+        .map(Json)?;
+
+    let options = match core_options().to_cors() {
+        Ok(a) => a,
+        Err(a) => return Ok(Err(a)),
+    };
+    Ok(options.respond_owned(move |guard| guard.responder(out)))
+}
+
+async fn run_migrations(rocket: Rocket<Build>) -> Rocket<Build> {
+    use diesel_migrations::MigrationHarness;
+
+    Db::get_one(&rocket)
+        .await
+        .expect("database connection")
+        .run(|conn| {
+            conn.run_pending_migrations(db::MIGRATIONS)
+                .expect("diesel migrations");
+        })
+        .await;
+
+    rocket
+}
+
+pub fn viewport_data() -> AdHoc {
+    AdHoc::on_ignite("Map viewport", |rocket| async {
+        rocket
+            .attach(AdHoc::on_ignite("Diesel Migrations", run_migrations))
+            .mount("/viewport", routes![viewport])
+    })
+}